@@ -1,29 +1,41 @@
 #![feature(fs_try_exists)]
 
-use std::{error::Error, time::{Duration, Instant}, str::FromStr, sync::mpsc};
+use std::{error::Error, fs, path::PathBuf, process::exit, time::{Duration, Instant}, str::FromStr, sync::{Arc, atomic::{AtomicBool, Ordering}}};
 
 use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
 use requestty::{Answer, OnEsc, prompt_one, Question};
 use reqwest::Client;
-use thirtyfour::WebDriver;
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, Semaphore},
+};
 
 use mod_file::{
-    {search, search::ProcessingUrl},
+    search::ProcessingUrl,
     {utils_data, utils_data::time_to_human_time}, chrome_spawn::ChromeChild,
     cmd_line_parser,
-    cmd_line_parser::Scan, process_part1, process_part1::{add_ublock, connect_to_chrome_driver},
+    cmd_line_parser::Scan, direct_download, process_part1, process_part1::{add_ublock, Found, Resolved},
+    provider, provider::Provider,
+    rate_limit,
+    search_filter,
     static_data,
     thread_pool,
     utils_check,
 };
-use crate::mod_file::cmd_line_parser::Args;
+use crate::mod_file::cmd_line_parser::{Args, Backend, Downloader};
+use crate::mod_file::{
+    download_progress::DownloadProgress, driver_pool::DriverPool, episode_outcome, episode_outcome::EpisodeOutcome,
+    hls_downloader, html_parser, journal, journal::Journal,
+};
 use crate::mod_file::thread_pool::ThreadPool;
 use crate::mod_file::utils_check::AllPath;
-use crate::mod_file::web;
+use crate::mod_file::{web, web::DownloadContext};
 
 mod mod_file;
 
+// how often the download loop check that some job can still report
+const WORKER_CHECK: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let mut new_args = cmd_line_parser::Args::parse();
@@ -32,117 +44,309 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let _ = ask_keyword(&mut new_args);
 
+    rate_limit::init(&new_args.limit_rate, &new_args.limit_schedule)?;
+    // ffmpeg fetch the stream itself, only the native downloader go through the limiter
+    if rate_limit::is_enabled() && new_args.downloader == Downloader::Ffmpeg {
+        warn!("ffmpeg download can't be limited, switching to --downloader native");
+        new_args.downloader = Downloader::Native;
+    }
+
     info!("{}", new_args);
 
+    let provider = provider::select(&new_args)?;
+
     let thread = thread_pool::max_thread_check(&new_args)?;
 
+    web::init_retry_policy(&new_args);
+
     let client = Client::builder().build()?;
 
-    let path = utils_check::confirm_chrome_ffmpeg_ublock_presence().await?;
+    let path = utils_check::confirm_chrome_ffmpeg_ublock_presence(&new_args).await?;
 
-    let processing_url = setup_search_or_download(&mut new_args).await?;
+    let processing_url = setup_search_or_download(&mut new_args, provider).await?;
 
-    let _ = iter_over_url_found(&new_args, &path, processing_url, thread, &client).await?;
+    let _ = iter_over_url_found(&new_args, &path, processing_url, thread, &client, provider).await?;
 
     Ok(())
 }
 
-async fn start(url_test: &str, path: &AllPath, mut thread: usize, args: &Args, driver: WebDriver, client: &Client) -> Result<(), Box<dyn Error>> {
+async fn start(url_test: &str, path: &AllPath, thread: usize, args: &Args, chrome: &mut Option<ChromeChild>, client: &Client, provider: &dyn Provider) -> Result<(), Box<dyn Error>> {
     let before = Instant::now();
 
-    let (save_path, good, error) = process_part1::scan_main(&driver, url_test, path, &client, args).await?;
+    info!("Start Processing with {} threads", thread);
+
+    let progress = DownloadProgress::new(0);
+    let ctx = DownloadContext {
+        ffmpeg: path.ffmpeg_path.clone(),
+        resume_path: path.resume_path.clone(),
+        log_path: path.log_path.clone(),
+        client: client.clone(),
+        handle: Handle::current(),
+        // -t stay the total connection budget even when there is less episode than thread
+        connections: Arc::new(Semaphore::new(thread)),
+        progress: progress.clone(),
+        attempt: 0,
+        debug: args.debug,
+    };
+
+    // an episode is downloaded as soon as its playlist is saved, while the next ones are resolved
+    let (found, resolved) = unbounded_channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let scan = {
+        let progress = progress.clone();
+        let cancelled = Arc::clone(&cancelled);
+        async move {
+            let scan = scan_url(url_test, path, args, chrome, client, provider, &found).await;
+            if let Ok((_, good, error)) = &scan {
+                // the downloads keep going while the user answer
+                if !progress.hide(|| process_part1::prevent_case_nothing_found_or_error(*good, *error, args)) {
+                    warn!("Cancelled, waiting for the episodes already downloading");
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+            }
+            scan
+        }
+    };
+    let (scan, downloads) = tokio::join!(scan, download_resolved(resolved, thread, ctx, path, args, Arc::clone(&cancelled)));
+    let (_, good, error) = scan?;
 
-    process_part1::prevent_case_nothing_found_or_error(good, error, args);
+    progress.finish();
 
-    process_part1::shutdown_chrome(args, &driver).await;
+    episode_outcome::print_summary(&downloads.outcomes);
 
-    if thread > good as usize {
-        warn!("update thread count from {thread} to {good}");
-        thread = good as usize;
+    if cancelled.load(Ordering::Relaxed) {
+        exit(130);
     }
 
-    let (mut vec_m3u8_path_folder, vec_save_path_vlc) =
-        process_part1::build_vec_m3u8_folder_path(path, save_path)?;
+    process_part1::build_vlc_playlist(good, args, downloads.save_path_vlc)?;
 
-    utils_data::custom_sort(&mut vec_m3u8_path_folder);
+    process_part1::end_print(before, path, good, error);
 
-    info!("Start Processing with {} threads", thread);
+    Ok(())
+}
 
-    let progress_bar = ProgressBar::new(good as u64);
-    progress_bar.enable_steady_tick(Duration::from_secs(1));
-
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:60.cyan/blue} {pos}/{len} ({eta})")?
-            .progress_chars("$>-"),
-    );
-
-    let (tx, rx) = mpsc::channel();
-    let mut pool = ThreadPool::new(thread, good as usize);
-    for (output_path, name) in vec_m3u8_path_folder {
-        let tx = tx.clone();
-        let ffmpeg = path.ffmpeg_path.clone();
-        let debug = args.debug.clone();
-        pool.execute(move || {
-            tx.send(web::download_build_video(
-                &output_path.to_str().unwrap(),
-                name.to_str().unwrap(),
-                &ffmpeg,
-                &debug,
-            ))
-                .unwrap_or(())
-        })
+// what was queued, and what came back from the workers
+struct Downloads {
+    pool: ThreadPool,
+    tx: UnboundedSender<EpisodeOutcome>,
+    // every queued job hold a clone, when only this one is left no job can report anymore
+    alive: Arc<()>,
+    ctx: DownloadContext,
+    downloader: Downloader,
+    save_path: String,
+    save_path_vlc: Vec<(PathBuf, String)>,
+    pending: Vec<PathBuf>,
+    outcomes: Vec<EpisodeOutcome>,
+    // set when the user refuse to go on with missing episodes, jobs not started yet do nothing
+    cancelled: Arc<AtomicBool>,
+}
+
+// until the scan is over and every queued episode reported, a truncated episode go back in the queue until --redownload is reached
+async fn download_resolved(mut resolved: UnboundedReceiver<Resolved>, thread: usize, ctx: DownloadContext, path: &AllPath, args: &Args, cancelled: Arc<AtomicBool>) -> Downloads {
+    let (tx, mut rx) = unbounded_channel();
+    let mut downloads = Downloads {
+        pool: ThreadPool::new(thread),
+        tx,
+        alive: Arc::new(()),
+        ctx,
+        downloader: args.downloader,
+        save_path: String::new(),
+        save_path_vlc: vec![],
+        pending: vec![],
+        outcomes: vec![],
+        cancelled,
+    };
+    let mut scanning = true;
+
+    while scanning || !downloads.pending.is_empty() {
+        tokio::select! {
+            next = resolved.recv(), if scanning => match next {
+                Some(Resolved::SavePath(save_path)) => downloads.save_path = save_path,
+                Some(Resolved::Playlist(file)) => downloads.add(file, path),
+                None => scanning = false,
+            },
+            Some(outcome) = rx.recv() => downloads.finished(outcome, path, args),
+            _ = tokio::time::sleep(WORKER_CHECK), if !downloads.pending.is_empty() => {
+                if Arc::strong_count(&downloads.alive) == 1 {
+                    // no job left, what they sent is already in the channel
+                    match rx.try_recv() {
+                        Ok(outcome) => downloads.finished(outcome, path, args),
+                        Err(_) => downloads.lost(),
+                    }
+                }
+            }
+        }
+    }
+    downloads
+}
+
+impl Downloads {
+    fn add(&mut self, file: PathBuf, path: &AllPath) {
+        let Some(name) = process_part1::episode_output(path, &self.save_path, &file) else {
+            return;
+        };
+        self.save_path_vlc.push((name.clone(), self.save_path.clone()));
+        self.ctx.progress.add_episode();
+
+        if journal::is_finished(&path.resume_path, &name) {
+            info!("Already downloaded: {}", name.display());
+            self.ctx.progress.skip();
+            self.outcomes.push(EpisodeOutcome::already_done(&name));
+            return;
+        }
+        self.queue(file, name, self.ctx.clone());
     }
 
-    drop(tx);
+    fn finished(&mut self, outcome: EpisodeOutcome, path: &AllPath, args: &Args) {
+        self.pending.retain(|name| *name != outcome.output);
 
-    for _ in rx.iter().take(good as usize) {
-        progress_bar.inc(1);
+        if !outcome.need_redownload(args.redownload) {
+            self.outcomes.push(outcome);
+            return;
+        }
+        warn!(
+            "Re-download {} ({}/{}): {}",
+            outcome.name,
+            outcome.attempt + 1,
+            args.redownload,
+            outcome.verify_error.as_deref().unwrap_or_default()
+        );
+        let _ = fs::remove_file(&outcome.output);
+        Journal::discard(&Journal::dir(&path.resume_path, outcome.output.to_str().unwrap()));
+
+        let mut ctx = self.ctx.clone();
+        ctx.attempt = outcome.attempt + 1;
+        self.ctx.progress.retry();
+        self.queue(outcome.playlist, outcome.output, ctx);
     }
 
-    progress_bar.finish();
+    fn lost(&mut self) {
+        for name in self.pending.drain(..) {
+            let mut lost = EpisodeOutcome::new(name.to_str().unwrap_or_default(), "");
+            lost.error = Some("download job stopped without reporting".to_string());
+            error!("{} failed: {}", lost.name, lost.error.as_deref().unwrap_or_default());
+            self.outcomes.push(lost);
+        }
+    }
 
-    process_part1::build_vlc_playlist(good, args, vec_save_path_vlc)?;
+    fn queue(&mut self, output_path: PathBuf, name: PathBuf, ctx: DownloadContext) {
+        let tx = self.tx.clone();
+        let alive = Arc::clone(&self.alive);
+        let downloader = self.downloader;
+        let cancelled = Arc::clone(&self.cancelled);
+        self.pending.push(name.clone());
+        self.pool.execute(move || {
+            let _alive = alive;
+            let outcome = match downloader {
+                _ if cancelled.load(Ordering::Relaxed) => {
+                    let mut outcome = EpisodeOutcome::new(name.to_str().unwrap(), output_path.to_str().unwrap());
+                    outcome.error = Some("cancelled".to_string());
+                    outcome
+                }
+                // a plain video is a file to copy, not a stream
+                _ if direct_download::is_link(&output_path) => direct_download::download_direct(
+                    output_path.to_str().unwrap(),
+                    name.to_str().unwrap(),
+                    &ctx,
+                ),
+                Downloader::Ffmpeg => web::download_build_video(
+                    output_path.to_str().unwrap(),
+                    name.to_str().unwrap(),
+                    &ctx,
+                ),
+                Downloader::Native => hls_downloader::download_native(
+                    output_path.to_str().unwrap(),
+                    name.to_str().unwrap(),
+                    &ctx,
+                ),
+            };
+            tx.send(outcome).unwrap_or(())
+        });
+    }
+}
 
-    process_part1::end_print(before, path, good, error);
+// try without browser first, chrome is only started for what the http backend can't read
+async fn scan_url(url_test: &str, path: &AllPath, args: &Args, chrome: &mut Option<ChromeChild>, client: &Client, provider: &dyn Provider, found: &Found) -> Result<(String, u16, u16), Box<dyn Error>> {
+    if args.backend != Backend::Browser {
+        match process_part1::scan_main_http(url_test, path, client, args, provider, found).await {
+            Ok(Some((save_path, good, error, failed))) => {
+                if failed.is_empty() || args.backend == Backend::Http {
+                    return Ok((save_path, good, error + failed.len() as u16));
+                }
+                warn!("{} episode(s) can't be read without browser, start chrome", failed.len());
+                let mut pool = open_browser(path, args, chrome, &failed[0]).await?;
+                let (resolved, not_resolved) = html_parser::enter_iframe_wait_jwplayer(&mut pool, args, failed, client, path, provider, found).await?;
+                pool.shutdown(args).await;
+                return Ok((save_path, good + resolved, error + not_resolved));
+            }
+            Ok(None) if args.backend == Backend::Http => {
+                return Err(format!("{url_test} can't be read without browser, retry with --backend auto").into());
+            }
+            Err(e) if args.backend == Backend::Http => return Err(e),
+            Ok(None) => warn!("{url_test} can't be read without browser, start chrome"),
+            Err(e) => warn!("http backend failed ({e}), start chrome"),
+        }
+    }
 
-    Ok(())
+    let mut pool = open_browser(path, args, chrome, url_test).await?;
+    let scan = process_part1::scan_main(&mut pool, url_test, path, client, args, provider, found).await?;
+    pool.shutdown(args).await;
+    Ok(scan)
 }
 
-async fn iter_over_url_found(new_args: &Args, path: &AllPath, processing_url: Vec<ProcessingUrl>, thread: usize, client: &Client) -> Result<(), Box<dyn Error>>{
-    time_it!("Global time:", {
-        if new_args.debug {
+async fn open_browser(path: &AllPath, args: &Args, chrome: &mut Option<ChromeChild>, url: &str) -> Result<DriverPool, Box<dyn Error>> {
+    if chrome.is_none() {
+        if args.debug {
             debug!("spawn chrome process");
         }
 
-        let mut child = ChromeChild::spawn(&path.chrome_path);
-        if new_args.debug {
-            debug!("wait 1sec chrome process spawn correctly");
+        let mut child = ChromeChild::spawn(&path.chrome_path, args.driver_port)?;
+        if args.debug {
+            debug!("wait chrome driver ready on port {}", child.port);
         }
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        child.wait_ready().await?;
+        *chrome = Some(child);
+    }
+
+    let driver_url = chrome.as_ref().unwrap().url();
+    DriverPool::new(args, add_ublock(args, path)?, &driver_url, url).await
+}
+
+async fn iter_over_url_found(new_args: &Args, path: &AllPath, processing_url: Vec<ProcessingUrl>, thread: usize, client: &Client, provider: &dyn Provider) -> Result<(), Box<dyn Error>>{
+    time_it!("Global time:", {
+        let mut chrome = None;
 
         for (index, x) in processing_url.iter().enumerate() {
             header!("Step {} / {}", index + 1, processing_url.len());
             info!("Process: {}", x.url);
-            let driver = connect_to_chrome_driver(&new_args, add_ublock(&new_args, &path)?, &x.url).await?;
 
-            start(&x.url, &path, thread, &new_args, driver, client).await?;
+            start(&x.url, &path, thread, &new_args, &mut chrome, client, provider).await?;
         }
 
-        child.chrome.kill()?;
+        if let Some(mut child) = chrome {
+            child.chrome.kill()?;
+        }
     });
 
     Ok(())
 }
 
-async fn setup_search_or_download(new_args: &mut Args) -> Result<Vec<ProcessingUrl>, Box<dyn Error>>{
+async fn setup_search_or_download(new_args: &mut Args, provider: &dyn Provider) -> Result<Vec<ProcessingUrl>, Box<dyn Error>>{
 
     let processing_url = match new_args.url_or_search_word {
         Scan::Search(ref keyword) => {
-            let find = search::search_over_json(&keyword, &new_args.language, &new_args.debug).await?;
+            let find = provider.search(&keyword, new_args).await?;
             build_print_nb_ep_film(&find);
-            let answer = build_question(&find)?;
-            find_real_link_with_answer(&find, answer)
+            if new_args.all {
+                find
+            } else if let Some(select) = &new_args.select {
+                utils_check::pick_season_list(select, find)?
+            } else if new_args.best_match {
+                utils_check::pick_best_match(keyword, find)?
+            } else {
+                let answer = build_question(&find)?;
+                find_real_link_with_answer(&find, answer)
+            }
         }
 
         Scan::Download(ref url) => {
@@ -151,6 +355,8 @@ async fn setup_search_or_download(new_args: &mut Args) -> Result<Vec<ProcessingU
                 ep: "".to_string(),
                 url: url.to_string(),
                 genre: "".to_string(),
+                matched: None,
+                score: None,
             }]
         }
     };
@@ -172,11 +378,14 @@ fn build_question(find: &Vec<ProcessingUrl>) -> requestty::Result<Answer> {
         .message("What seasons do you want?")
         .choices(
             find.iter()
-                .map(|s| {
+                .enumerate()
+                .map(|(i, s)| {
                     let tmp_genre = s.clone().genre;
+                    // same number as --select
                     format!(
-                        "{} ({})\n[{}]",
-                        s.name,
+                        "{}: {} ({})\n[{}]",
+                        i + 1,
+                        s.label(),
                         s.ep,
                         if tmp_genre.is_empty() {
                             String::from("no tag found")
@@ -226,7 +435,8 @@ fn build_print_nb_ep_film(find: &Vec<ProcessingUrl>){
 }
 
 fn ask_keyword(new_args: &mut Args) -> Result<(), Box<dyn Error>> {
-    if new_args.url_or_search_word.is_empty() {
+    // filters alone are enough to browse the catalogue
+    if new_args.url_or_search_word.is_empty() && !search_filter::is_set(new_args) {
         warn!("prefers use ./{} -h", utils_data::exe_name());
         if let Ok(reply) = utils_data::ask_keyword("Enter url to direct download or keyword to search: ")
         {
@@ -1,12 +1,33 @@
 pub(crate) mod chrome_spawn;
 pub(crate) mod cmd_line_parser;
+pub(crate) mod direct_download;
+pub(crate) mod download_progress;
+pub(crate) mod driver_pool;
+pub(crate) mod driver_provision;
+pub(crate) mod embed_extractor;
+pub(crate) mod episode_outcome;
+#[cfg(target_os = "linux")]
+pub(crate) mod ffmpeg_provision;
+pub(crate) mod hls_downloader;
 pub(crate) mod html_parser;
+pub(crate) mod http_scraper;
+pub(crate) mod journal;
 pub(crate) mod log_color;
+pub(crate) mod network_capture;
+pub(crate) mod neko_sama;
 pub(crate) mod process_part1;
+pub(crate) mod provider;
+pub(crate) mod quality;
+pub(crate) mod rate_limit;
 pub(crate) mod search;
+pub(crate) mod search_filter;
+pub(crate) mod search_index;
 pub(crate) mod static_data;
+#[cfg(test)]
+pub(crate) mod test_server;
 pub(crate) mod thread_pool;
 pub(crate) mod utils_check;
 pub(crate) mod utils_data;
+pub(crate) mod video_check;
 pub(crate) mod vlc_playlist_builder;
 pub(crate) mod web;
@@ -1,17 +1,31 @@
 use std::{
+    error::Error,
+    net::TcpListener,
     path::PathBuf,
     process::{Child, Command},
+    time::{Duration, Instant},
 };
 
+use reqwest::Client;
+use serde_json::Value;
+
+// chromedriver start in a few hundred ms, a slow machine or antivirus scan can take much more
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct ChromeChild {
     pub chrome: Child,
+    pub port: u16,
 }
 
 impl ChromeChild {
-    pub fn spawn(chrome: &PathBuf) -> Self {
+    pub fn spawn(chrome: &PathBuf, port: Option<u16>) -> Result<Self, Box<dyn Error>> {
+        let port = match port {
+            Some(port) => check_port_free(port)?,
+            None => free_port()?,
+        };
         let child_process = Command::new(chrome)
             .args([
-                "--port=6969",
+                &format!("--port={port}"),
                 "--ignore-certificate-errors",
                 "--disable-logging",
                 "--disable-in-process-stack-traces",
@@ -19,10 +33,55 @@ impl ChromeChild {
                 "--silent",
             ])
             .spawn()
-            .expect("Can't spawn chromeDriver");
-        ChromeChild {
+            .map_err(|e| format!("Can't spawn chromeDriver {}: {e}", chrome.display()))?;
+        Ok(ChromeChild {
             chrome: child_process,
+            port,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+
+    // poll /status until the driver accept sessions instead of guessing how long it take to start
+    pub async fn wait_ready(&mut self) -> Result<(), Box<dyn Error>> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(1))
+            .build()?;
+        let status = format!("{}/status", self.url());
+        let start = Instant::now();
+
+        while start.elapsed() < READY_TIMEOUT {
+            if let Some(exit) = self.chrome.try_wait()? {
+                return Err(format!("chromedriver exit before being ready ({exit}), port {} may be taken", self.port).into());
+            }
+            if let Ok(resp) = client.get(&status).send().await {
+                if let Ok(json) = serde_json::from_str::<Value>(&resp.text().await.unwrap_or_default()) {
+                    if json["value"]["ready"].as_bool().unwrap_or(false) {
+                        return Ok(());
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
         }
+        Err(format!("chromedriver not ready after {}s on port {}", READY_TIMEOUT.as_secs(), self.port).into())
+    }
+}
+
+// the os give a free port, it's released right away for chromedriver
+fn free_port() -> Result<u16, Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+fn check_port_free(port: u16) -> Result<u16, Box<dyn Error>> {
+    match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(_) => Ok(port),
+        Err(e) => Err(format!(
+            "Port {port} is already used ({e}), another run or an old chromedriver is still alive, kill it or use another --driver-port"
+        )
+            .into()),
     }
 }
 
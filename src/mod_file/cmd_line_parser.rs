@@ -1,7 +1,10 @@
 use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
 use std::str::FromStr;
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, ValueEnum};
+
+use crate::mod_file::{quality::Quality, search_filter::{self, YearRange}, static_data};
 
 #[derive(Parser, Debug)]
 #[command(author = "PsykoDev", version, about, long_about = None)]
@@ -60,6 +63,231 @@ pub struct Args {
     action = ArgAction::SetTrue
     )]
     pub minimized_chrome: bool,
+
+    #[arg(
+    long = "headless",
+    default_value_t = false,
+    help = "run chrome without window, for servers without display, ublock still loaded [default: false]",
+    action = ArgAction::SetTrue
+    )]
+    pub headless: bool,
+
+    #[arg(
+    long = "driver-port",
+    help = "chromedriver port, fail if already used [default: a free port chosen automatically]"
+    )]
+    pub driver_port: Option<u16>,
+
+    #[arg(
+    long = "driver-index",
+    default_value = static_data::CHROMEDRIVER_INDEX,
+    help = "chrome for testing json index used to download the chromedriver matching your chrome"
+    )]
+    pub driver_index: String,
+
+    #[arg(
+    long = "download-ffmpeg",
+    default_value_t = false,
+    help = "linux only, download a static ffmpeg build in utils when no ffmpeg 4+ is found [default: false]",
+    action = ArgAction::SetTrue
+    )]
+    pub download_ffmpeg: bool,
+
+    #[arg(
+    long = "sessions",
+    default_value_t = 2,
+    help = "browser sessions reading episode pages at the same time when chrome is needed"
+    )]
+    pub sessions: u8,
+
+    #[arg(
+    long = "player-timeout",
+    default_value_t = 30,
+    help = "seconds to wait for the video player of an episode page before giving up on it"
+    )]
+    pub player_timeout: u64,
+
+    #[arg(
+    long = "resolver",
+    value_enum,
+    default_value_t = Resolver::Auto,
+    help = "how chrome find the stream, jwplayer ask the player, network watch the requests of the page, auto try jwplayer then network"
+    )]
+    pub resolver: Resolver,
+
+    #[arg(
+    long = "downloader",
+    value_enum,
+    default_value_t = Downloader::Ffmpeg,
+    help = "ffmpeg let ffmpeg fetch the stream, native download every segment in rust then only remux with ffmpeg"
+    )]
+    pub downloader: Downloader,
+
+    #[arg(
+    long = "limit-rate",
+    help = "bandwidth limit shared by all threads like 500K, 5M or 1G, use the native downloader when set [default: unlimited]"
+    )]
+    pub limit_rate: Option<String>,
+
+    #[arg(
+    long = "limit-schedule",
+    help = "file with one \"HH:MM-HH:MM RATE\" rule by line, override --limit-rate during these hours"
+    )]
+    pub limit_schedule: Option<PathBuf>,
+
+    #[arg(
+    long = "retries",
+    default_value_t = 3,
+    help = "retry of every network call on 5xx, 429, timeout or connection reset"
+    )]
+    pub retries: u8,
+
+    #[arg(
+    long = "retry-delay",
+    default_value_t = 500,
+    help = "first retry delay in milliseconds, doubled at each retry with random jitter"
+    )]
+    pub retry_delay: u64,
+
+    #[arg(
+    long = "timeout",
+    default_value_t = 60,
+    help = "seconds to wait for an answer, then for each part of the body, a slow download isn't cut while data keep coming"
+    )]
+    pub timeout: u64,
+
+    #[arg(
+    long = "redownload",
+    default_value_t = 2,
+    help = "re-download an episode when ffprobe find it truncated or without audio/video, 0 to disable"
+    )]
+    pub redownload: u8,
+
+    #[arg(
+    short = 'q',
+    long = "quality",
+    default_value = "best",
+    help = "best, worst, 1080p, max-height=720 or max-bandwidth=3000000, fallback on the next variant if not available"
+    )]
+    pub quality: Quality,
+
+    #[arg(
+    long = "backend",
+    value_enum,
+    default_value_t = Backend::Auto,
+    help = "auto read pages without browser then start chrome only if needed, http never start chrome, browser always use chrome"
+    )]
+    pub backend: Backend,
+
+    #[arg(
+    long = "provider",
+    help = "site to search or download from, neko-sama, by default taken from the url host"
+    )]
+    pub provider: Option<String>,
+
+    #[arg(
+    long = "select",
+    conflicts_with_all = ["all", "best_match"],
+    help = "seasons to download from the search results without prompt, by number like 1,3-5"
+    )]
+    pub select: Option<String>,
+
+    #[arg(
+    long = "all",
+    default_value_t = false,
+    conflicts_with = "best_match",
+    help = "download every season found by the search without prompt [default: false]",
+    action = ArgAction::SetTrue
+    )]
+    pub all: bool,
+
+    #[arg(
+    long = "best-match",
+    default_value_t = false,
+    help = "download only the season named like the keyword without prompt, error if several match [default: false]",
+    action = ArgAction::SetTrue
+    )]
+    pub best_match: bool,
+
+    #[arg(
+    long = "index-ttl",
+    default_value_t = 24,
+    help = "hours the search index saved in utils/cache is used without asking the site, 0 to always check"
+    )]
+    pub index_ttl: u64,
+
+    #[arg(
+    long = "refresh-index",
+    default_value_t = false,
+    help = "download the search index again even if the cached one is fresh [default: false]",
+    action = ArgAction::SetTrue
+    )]
+    pub refresh_index: bool,
+
+    #[arg(
+    long = "type",
+    value_delimiter = ',',
+    help = "search only this kind of season, tv, movie, ova... several with tv,ova"
+    )]
+    pub season_type: Vec<String>,
+
+    #[arg(
+    long = "status",
+    value_enum,
+    help = "search only airing or finished seasons"
+    )]
+    pub status: Option<SeasonStatus>,
+
+    #[arg(
+    long = "genre",
+    value_delimiter = ',',
+    help = "search only seasons with every genre given, action,comedy"
+    )]
+    pub genre: Vec<String>,
+
+    #[arg(
+    long = "year",
+    help = "search only seasons started this year or in a range, 2015, 2015..2020, 2015.. or ..2020"
+    )]
+    pub year: Option<YearRange>,
+
+    #[arg(
+    long = "min-score",
+    help = "search only seasons scored at least this, 7.5"
+    )]
+    pub min_score: Option<f64>,
+
+    #[arg(
+    long = "limit",
+    help = "show only the most relevant search results, 20"
+    )]
+    pub limit: Option<usize>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Downloader {
+    Ffmpeg,
+    Native,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Auto,
+    Http,
+    Browser,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Resolver {
+    Auto,
+    Jwplayer,
+    Network,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum SeasonStatus {
+    Airing,
+    Finished,
 }
 
 #[derive(Debug, Clone)]
@@ -80,9 +308,9 @@ impl Scan {
 impl FromStr for Scan {
     type Err = String;
     fn from_str(s: &str) -> Result<Scan, String> {
-        if s.starts_with("https://neko-sama.fr") {
+        if s.starts_with("https://") || s.starts_with("http://") {
             let Ok(uri) = http::uri::Uri::from_str(s) else {
-                return Err("Ill formed neko-sama uri".to_owned());
+                return Err("Ill formed uri".to_owned());
             };
             Ok(Scan::Download(uri))
         } else {
@@ -103,6 +331,23 @@ impl Display for Args {
                   Vlc playlist:\t{}\n\
                   Show Alert:\t{}\n\
                   Minimized:\t{}\n\
+                  Headless:\t{}\n\
+                  Driver port:\t{}\n\
+                  Sessions:\t{} (player timeout {}s)\n\
+                  Resolver:\t{:?}\n\
+                  Driver index:\t{}\n\
+                  Get ffmpeg:\t{}\n\
+                  Downloader:\t{:?}\n\
+                  Limit rate:\t{}\n\
+                  Retries:\t{} (first after {}ms, timeout {}s)\n\
+                  Re-download:\t{}\n\
+                  Quality:\t{}\n\
+                  Backend:\t{:?}\n\
+                  Provider:\t{}\n\
+                  Selection:\t{}\n\
+                  Search index:\t{}\n\
+                  Filters:\t{}\n\
+                  Limit:\t\t{}\n\
                   Debug:\t\t{}",
             self.url_or_search_word,
             self.language,
@@ -110,6 +355,31 @@ impl Display for Args {
             self.vlc_playlist,
             self.ignore_alert_missing_episode,
             self.minimized_chrome,
+            self.headless,
+            self.driver_port.map(|p| p.to_string()).unwrap_or("auto".to_string()),
+            self.sessions,
+            self.player_timeout,
+            self.resolver,
+            self.driver_index,
+            self.download_ffmpeg,
+            self.downloader,
+            self.limit_rate.as_deref().unwrap_or("unlimited"),
+            self.retries,
+            self.retry_delay,
+            self.timeout,
+            self.redownload,
+            self.quality,
+            self.backend,
+            self.provider.as_deref().unwrap_or("from url"),
+            match (&self.select, self.all, self.best_match) {
+                (Some(select), _, _) => select.as_str(),
+                (None, true, _) => "all",
+                (None, false, true) => "best match",
+                (None, false, false) => "prompt",
+            },
+            if self.refresh_index { "refresh".to_string() } else { format!("cached {}h", self.index_ttl) },
+            search_filter::describe(self),
+            self.limit.map(|l| l.to_string()).unwrap_or("none".to_string()),
             self.debug,
         )
     }
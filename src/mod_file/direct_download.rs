@@ -0,0 +1,227 @@
+use std::{
+    error::Error,
+    fs,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use reqwest::{header::RANGE, Client, StatusCode};
+
+use crate::{debug, warn};
+use crate::mod_file::{
+    download_progress::EpisodeProgress, episode_outcome::EpisodeOutcome, hls_downloader, web, web::DownloadContext,
+};
+
+// a video that isn't a playlist is saved in tmp/ as <episode>.link, its url then the headers like a playlist
+pub const LINK_EXTENSION: &str = "link";
+
+pub fn save_link(url: &str, file_name: &str, tmp_dl: &Path, headers: &[(String, String)]) -> Result<PathBuf, Box<dyn Error>> {
+    let file = tmp_dl.join(format!("{file_name}.{LINK_EXTENSION}"));
+    fs::write(&file, hls_downloader::with_headers(format!("{url}\n").as_bytes(), headers))?;
+    Ok(file)
+}
+
+pub fn is_link(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == LINK_EXTENSION)
+}
+
+#[derive(Debug, PartialEq)]
+struct Link {
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+fn read_link(path: &str) -> Result<Link, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let url = content.lines().next().unwrap_or_default().trim();
+    if url.is_empty() {
+        return Err(format!("no url in {path}").into());
+    }
+    Ok(Link {
+        url: url.to_string(),
+        headers: hls_downloader::playlist_headers(path),
+    })
+}
+
+// no ffmpeg needed, the file is written in <name>.part and renamed once complete
+pub fn download_direct(path: &str, name: &str, ctx: &DownloadContext) -> EpisodeOutcome {
+    let time = Instant::now();
+    let mut outcome = EpisodeOutcome::new(name, path);
+    let part = PathBuf::from(format!("{name}.part"));
+
+    if ctx.debug {
+        debug!("save path: {} output name: {}", path, name);
+    }
+
+    // one connection for the whole file, like ffmpeg
+    let _permit = ctx.handle.block_on(ctx.connections.acquire()).expect("connection budget closed");
+
+    let progress = ctx.progress.episode(&outcome.name, 0);
+    let fetched = read_link(path).and_then(|link| {
+        ctx.handle
+            .block_on(fetch_to_part(&ctx.client, &link.url, &link.headers, &part, &progress, ctx.debug))
+    });
+    progress.done();
+    if let Err(e) = fetched.and_then(|_| Ok(fs::rename(&part, name)?)) {
+        outcome.error = Some(format!("Can't download video: {e}"));
+    }
+
+    outcome.finish(time, ctx)
+}
+
+// a .part left by a previous run or a cut connection is continued with a Range request
+async fn fetch_to_part(client: &Client, url: &str, headers: &[(String, String)], part: &Path, progress: &EpisodeProgress, debug: bool) -> Result<(), Box<dyn Error>> {
+    let retries = web::retry_policy().retries;
+    let mut attempt = 0;
+    loop {
+        let mut done = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+        let mut request_headers = headers.to_vec();
+        if done > 0 {
+            request_headers.push((RANGE.to_string(), format!("bytes={done}-")));
+        }
+
+        let mut resp = web::web_request_with_headers(client, url, &request_headers).await?;
+        let mut file = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => OpenOptions::new().append(true).open(part)?,
+            // the server ignore Range, start again
+            StatusCode::OK => {
+                done = 0;
+                File::create(part)?
+            }
+            status => return Err(format!("{url}: {status}").into()),
+        };
+        if debug && done > 0 {
+            debug!("resume {} from {} bytes", url, done);
+        }
+        // content-length is only what is left
+        progress.set_length(done + resp.content_length().unwrap_or(0));
+
+        let cut = loop {
+            match web::next_chunk(&mut resp).await {
+                Ok(Some(chunk)) => {
+                    file.write_all(&chunk)?;
+                    done += chunk.len() as u64;
+                    progress.update(done, done);
+                }
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            }
+        };
+        match cut {
+            None => return Ok(()),
+            Some(e) if attempt < retries => {
+                attempt += 1;
+                warn!("{} cut at {} bytes ({}), resume {}/{}", url, done, e, attempt, retries);
+            }
+            Some(e) => return Err(format!("{url}: {e}").into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mod_file::{
+        download_progress::DownloadProgress,
+        test_server::{temp_dir, Reply, TestServer},
+    };
+
+    const VIDEO: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    // answer Range like a real host when `ranges` is set
+    fn video_server(ranges: bool) -> TestServer {
+        TestServer::start(move |req| {
+            let start = req
+                .headers
+                .get("range")
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+            match start {
+                Some(start) if ranges => Reply {
+                    status: 206,
+                    headers: vec![(
+                        "Content-Range".to_string(),
+                        format!("bytes {start}-{}/{}", VIDEO.len() - 1, VIDEO.len()),
+                    )],
+                    body: VIDEO[start..].to_vec(),
+                },
+                _ => Reply::ok(VIDEO),
+            }
+        })
+    }
+
+    async fn fetch(server: &TestServer, part: &Path) -> Result<(), Box<dyn Error>> {
+        let progress = DownloadProgress::new(1).episode("ep1", 0);
+        let headers = [("Referer".to_string(), "https://player.example/".to_string())];
+        let fetched = fetch_to_part(&Client::new(), &server.url("/ep1.mp4"), &headers, part, &progress, false).await;
+        progress.done();
+        fetched
+    }
+
+    #[test]
+    fn link_keep_the_url_and_the_headers() {
+        let dir = temp_dir("link");
+        let headers = vec![("Referer".to_string(), "https://player.example/".to_string())];
+        let link = save_link("https://cdn.example/ep1.mp4?t=1", "ep1", &dir, &headers).unwrap();
+
+        assert_eq!(link, dir.join("ep1.link"));
+        assert!(is_link(&link));
+        assert_eq!(
+            read_link(link.to_str().unwrap()).unwrap(),
+            Link {
+                url: "https://cdn.example/ep1.mp4?t=1".to_string(),
+                headers
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn video_is_saved_as_a_plain_file() {
+        let dir = temp_dir("direct");
+        let server = video_server(true);
+        let part = dir.join("ep1.mp4.part");
+
+        fetch(&server, &part).await.unwrap();
+        assert_eq!(fs::read(&part).unwrap(), VIDEO);
+        let request = &server.requests("/ep1.mp4")[0];
+        assert_eq!(request.headers.get("referer").map(|r| r.as_str()), Some("https://player.example/"));
+        assert!(!request.headers.contains_key("range"));
+    }
+
+    #[tokio::test]
+    async fn a_part_file_is_continued() {
+        let dir = temp_dir("direct-resume");
+        let server = video_server(true);
+        let part = dir.join("ep1.mp4.part");
+        fs::write(&part, &VIDEO[..10]).unwrap();
+
+        fetch(&server, &part).await.unwrap();
+        assert_eq!(fs::read(&part).unwrap(), VIDEO);
+        assert_eq!(
+            server.requests("/ep1.mp4")[0].headers.get("range").map(|r| r.as_str()),
+            Some("bytes=10-")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_host_without_range_start_again() {
+        let dir = temp_dir("direct-no-range");
+        let server = video_server(false);
+        let part = dir.join("ep1.mp4.part");
+        fs::write(&part, b"stale bytes").unwrap();
+
+        fetch(&server, &part).await.unwrap();
+        assert_eq!(fs::read(&part).unwrap(), VIDEO);
+    }
+
+    #[tokio::test]
+    async fn a_missing_video_is_an_error() {
+        let dir = temp_dir("direct-missing");
+        let server = TestServer::start(|_| Reply::status(404));
+
+        let e = fetch(&server, &dir.join("ep1.mp4.part")).await.unwrap_err();
+        assert!(e.to_string().contains("404"), "{e}");
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use indicatif::{BinaryBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+// one bar by active episode plus an overall bar in bytes, ETA come from downloaded data not episode count
+#[derive(Clone)]
+pub struct DownloadProgress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    state: Arc<Mutex<Overall>>,
+}
+
+#[derive(Default)]
+struct Overall {
+    episodes: usize,
+    finished: usize,
+    next_id: usize,
+    // id -> (bytes downloaded, fraction done)
+    active: HashMap<usize, (u64, f64)>,
+    done_bytes: u64,
+}
+
+pub struct EpisodeProgress {
+    id: usize,
+    bar: ProgressBar,
+    started: Instant,
+    parent: DownloadProgress,
+}
+
+impl DownloadProgress {
+    pub fn new(episodes: usize) -> DownloadProgress {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(0));
+        overall.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {bar:60.cyan/blue} {bytes}/~{total_bytes} {binary_bytes_per_sec} ({eta}) {msg}")
+                .unwrap()
+                .progress_chars("$>-"),
+        );
+        overall.enable_steady_tick(Duration::from_secs(1));
+        overall.set_message(format!("0/{episodes} episodes"));
+
+        DownloadProgress {
+            multi,
+            overall,
+            state: Arc::new(Mutex::new(Overall {
+                episodes,
+                ..Default::default()
+            })),
+        }
+    }
+
+    // `len` is a segment count or a duration in milliseconds, whatever the downloader can follow
+    pub fn episode(&self, name: &str, len: u64) -> EpisodeProgress {
+        let bar = self.multi.add(ProgressBar::new(len.max(1)));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{prefix:40!} {bar:30.green/white} {percent:>3}% {msg}")
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        bar.set_prefix(name.to_string());
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.active.insert(id, (0, 0.0));
+
+        EpisodeProgress {
+            id,
+            bar,
+            started: Instant::now(),
+            parent: self.clone(),
+        }
+    }
+
+    // one more episode resolved, the count grow while the season is scanned
+    pub fn add_episode(&self) {
+        self.state.lock().unwrap().episodes += 1;
+        self.refresh();
+    }
+
+    // hide the bars while something is printed or asked
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.multi.suspend(f)
+    }
+
+    // suspend block every bar update, a question that wait for the user stop drawing instead
+    pub fn hide<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.multi.set_draw_target(ProgressDrawTarget::hidden());
+        let result = f();
+        self.multi.set_draw_target(ProgressDrawTarget::stderr());
+        result
+    }
+
+    // already downloaded episode, nothing to show
+    pub fn skip(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.episodes = state.episodes.saturating_sub(1);
+        drop(state);
+        self.refresh();
+    }
+
+    // the episode is downloaded again, its bytes stay in the total as they really went through the network
+    pub fn retry(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.finished = state.finished.saturating_sub(1);
+        drop(state);
+        self.refresh();
+    }
+
+    pub fn finish(&self) {
+        self.overall.finish();
+    }
+
+    fn refresh(&self) {
+        let state = self.state.lock().unwrap();
+        let active_bytes: u64 = state.active.values().map(|(b, _)| b).sum();
+        let downloaded = state.done_bytes + active_bytes;
+
+        // average size of episodes we know enough about, times the episode count
+        let known: Vec<f64> = state
+            .active
+            .values()
+            .filter(|(b, f)| *f > 0.01 && *b > 0)
+            .map(|(b, f)| *b as f64 / f)
+            .chain((state.finished > 0).then(|| state.done_bytes as f64 / state.finished as f64))
+            .collect();
+        if !known.is_empty() {
+            let average = known.iter().sum::<f64>() / known.len() as f64;
+            let estimate = (average * state.episodes as f64) as u64;
+            self.overall.set_length(estimate.max(downloaded));
+        }
+        self.overall.set_position(downloaded);
+        self.overall
+            .set_message(format!("{}/{} episodes", state.finished, state.episodes));
+    }
+}
+
+impl EpisodeProgress {
+    pub fn set_length(&self, len: u64) {
+        self.bar.set_length(len.max(1));
+    }
+
+    pub fn update(&self, pos: u64, bytes: u64) {
+        self.bar.set_position(pos);
+        let speed = bytes as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+        self.bar.set_message(format!(
+            "{} {}/s",
+            BinaryBytes(bytes),
+            BinaryBytes(speed as u64)
+        ));
+
+        let fraction = pos as f64 / self.bar.length().unwrap_or(1).max(1) as f64;
+        self.parent
+            .state
+            .lock()
+            .unwrap()
+            .active
+            .insert(self.id, (bytes, fraction.min(1.0)));
+        self.parent.refresh();
+    }
+
+    pub fn done(self) {
+        self.bar.finish_and_clear();
+        let mut state = self.parent.state.lock().unwrap();
+        let (bytes, _) = state.active.remove(&self.id).unwrap_or_default();
+        state.finished += 1;
+        state.done_bytes += bytes;
+        drop(state);
+        self.parent.refresh();
+    }
+}
@@ -0,0 +1,71 @@
+use std::error::Error;
+
+use thirtyfour::{ChromeCapabilities, WebDriver};
+
+use crate::{debug, warn};
+use crate::mod_file::{cmd_line_parser::Args, process_part1};
+
+// several sessions of the same chromedriver, each one resolve its own episodes
+pub struct DriverPool {
+    driver_url: String,
+    prefs: ChromeCapabilities,
+    pub sessions: Vec<WebDriver>,
+}
+
+impl DriverPool {
+    // only one session at first, the season page is read by one browser
+    pub async fn new(args: &Args, prefs: ChromeCapabilities, driver_url: &str, url: &str) -> Result<DriverPool, Box<dyn Error>> {
+        let first = process_part1::connect_to_chrome_driver(args, prefs.clone(), driver_url, url).await?;
+        Ok(DriverPool {
+            driver_url: driver_url.to_string(),
+            prefs,
+            sessions: vec![first],
+        })
+    }
+
+    pub fn first(&self) -> &WebDriver {
+        &self.sessions[0]
+    }
+
+    // open more sessions up to `size`, a session that can't start only reduce the pool
+    pub async fn grow(&mut self, size: usize, args: &Args) {
+        while self.sessions.len() < size {
+            match self.open(args).await {
+                Ok(driver) => self.sessions.push(driver),
+                Err(e) => {
+                    warn!("Can't open browser session {}: {e}", self.sessions.len() + 1);
+                    break;
+                }
+            }
+        }
+        if args.debug {
+            debug!("{} browser session(s)", self.sessions.len());
+        }
+    }
+
+    async fn open(&self, args: &Args) -> Result<WebDriver, Box<dyn Error>> {
+        process_part1::connect_to_chrome_driver(args, self.prefs.clone(), &self.driver_url, "about:blank").await
+    }
+
+    // a crashed session is replaced by a new one of the same driver
+    pub async fn restart(driver_url: &str, prefs: &ChromeCapabilities, session: &mut WebDriver, args: &Args) -> Result<(), Box<dyn Error>> {
+        let _ = session.clone().quit().await;
+        *session = process_part1::connect_to_chrome_driver(args, prefs.clone(), driver_url, "about:blank").await?;
+        Ok(())
+    }
+
+    pub fn parts(&mut self) -> (&str, &ChromeCapabilities, &mut Vec<WebDriver>) {
+        (&self.driver_url, &self.prefs, &mut self.sessions)
+    }
+
+    pub async fn shutdown(self, args: &Args) {
+        for driver in &self.sessions {
+            process_part1::shutdown_chrome(args, driver).await;
+        }
+    }
+}
+
+// a session answering commands is alive, the error came from the page
+pub async fn is_alive(driver: &WebDriver) -> bool {
+    driver.current_url().await.is_ok()
+}
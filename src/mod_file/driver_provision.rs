@@ -0,0 +1,316 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use reqwest::Client;
+use serde_json::Value;
+use zip::ZipArchive;
+
+use crate::{debug, info, warn};
+use crate::mod_file::{cmd_line_parser::Args, static_data, utils_check::AllPath, web};
+
+static VERSION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+\.\d+\.\d+\.\d+").unwrap());
+
+// Ok(true) when utils/ has a chromedriver usable with the installed chrome
+pub async fn ensure_chromedriver(path: &AllPath, args: &Args) -> Result<bool, Box<dyn Error>> {
+    match_chrome(chrome_version(), path, args).await
+}
+
+async fn match_chrome(chrome: Option<String>, path: &AllPath, args: &Args) -> Result<bool, Box<dyn Error>> {
+    let installed = driver_version(&path.chrome_path);
+    let Some(chrome) = chrome else {
+        if installed.is_some() {
+            warn!("Can't find chrome version, keep the chromedriver in utils");
+        }
+        return Ok(installed.is_some());
+    };
+    if args.debug {
+        debug!("chrome {chrome}, chromedriver {}", installed.as_deref().unwrap_or("missing"));
+    }
+
+    if let Some(installed) = &installed {
+        if major(installed) == major(&chrome) {
+            return Ok(true);
+        }
+        info!("Chrome updated to {chrome}, chromedriver {installed} need an update");
+    }
+
+    match provision(&chrome, path, &args.driver_index).await {
+        Ok(version) => {
+            info!("chromedriver {version} installed in {}", path.extract_path.display());
+            Ok(true)
+        }
+        // an old driver may still work, let chrome tell us
+        Err(e) if installed.is_some() => {
+            warn!("Can't update chromedriver: {e}");
+            Ok(true)
+        }
+        Err(e) => {
+            warn!("Can't download chromedriver: {e}");
+            Ok(false)
+        }
+    }
+}
+
+async fn provision(chrome: &str, path: &AllPath, index_url: &str) -> Result<String, Box<dyn Error>> {
+    let client = Client::new();
+    let index: Value = serde_json::from_slice(&web::fetch_bytes(&client, index_url).await?)?;
+    let (version, url) = pick_download(&index, chrome, static_data::DRIVER_PLATFORM).ok_or(format!(
+        "no chromedriver for chrome {chrome} ({}) in {index_url}",
+        static_data::DRIVER_PLATFORM
+    ))?;
+
+    info!("Download chromedriver {version}: {url}");
+    let archive = web::fetch_bytes(&client, &url).await?;
+    unpack_driver(&archive, &path.chrome_path)?;
+    Ok(version)
+}
+
+// same version if it exist, else the last build of the same major
+fn pick_download(index: &Value, chrome: &str, platform: &str) -> Option<(String, String)> {
+    let versions = index["versions"].as_array()?;
+    let with_driver: Vec<(String, String)> = versions
+        .iter()
+        .filter_map(|v| {
+            let url = v["downloads"]["chromedriver"]
+                .as_array()?
+                .iter()
+                .find(|d| d["platform"] == platform)?["url"]
+                .as_str()?
+                .to_string();
+            Some((v["version"].as_str()?.to_string(), url))
+        })
+        .collect();
+
+    if let Some(exact) = with_driver.iter().find(|(v, _)| v == chrome) {
+        return Some(exact.clone());
+    }
+    with_driver
+        .into_iter()
+        .filter(|(v, _)| major(v) == major(chrome))
+        .max_by_key(|(v, _)| parse_version(v))
+}
+
+// the archive contain a `chromedriver-<platform>/` folder, only the binary is kept
+fn unpack_driver(archive: &[u8], destination: &Path) -> Result<(), Box<dyn Error>> {
+    let binary = destination.file_name().and_then(|f| f.to_str()).unwrap_or("chromedriver");
+    let mut zip = ZipArchive::new(Cursor::new(archive))?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() || Path::new(entry.name()).file_name().and_then(|f| f.to_str()) != Some(binary) {
+            continue;
+        }
+        // write next to the old one then swap, a failed download keep the old driver
+        let tmp = destination.with_extension("part");
+        io::copy(&mut entry, &mut File::create(&tmp)?)?;
+        #[cfg(any(target_os = "macos", target_os = "linux"))]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+        }
+        fs::rename(&tmp, destination)?;
+        return Ok(());
+    }
+    Err(format!("no {binary} in the archive").into())
+}
+
+fn chrome_version() -> Option<String> {
+    #[cfg(target_os = "windows")]
+    {
+        use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+        if let Ok(key) = RegKey::predef(HKEY_CURRENT_USER).open_subkey("Software\\Google\\Chrome\\BLBeacon") {
+            if let Ok(version) = key.get_value::<String, _>("version") {
+                return Some(version);
+            }
+        }
+    }
+
+    static_data::CHROME_BINARIES
+        .iter()
+        .find_map(|bin| version_of(Command::new(bin).arg("--version")))
+}
+
+fn driver_version(driver: &PathBuf) -> Option<String> {
+    if !driver.exists() {
+        return None;
+    }
+    version_of(Command::new(driver).arg("--version"))
+}
+
+fn version_of(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    VERSION
+        .find(&String::from_utf8_lossy(&output.stdout))
+        .map(|v| v.as_str().to_string())
+}
+
+fn parse_version(v: &str) -> Vec<u32> {
+    v.split('.').filter_map(|n| n.parse().ok()).collect()
+}
+
+fn major(v: &str) -> Option<u32> {
+    parse_version(v).first().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use clap::Parser;
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+    use crate::mod_file::test_server::{all_path, temp_dir, Reply, TestServer};
+
+    fn index() -> Value {
+        serde_json::from_str(include_str!("../../tests/fixtures/chromedriver/known-good-versions.json")).unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    // a script answering --version like chromedriver
+    fn fake_driver(version: &str) -> Vec<u8> {
+        format!("#!/bin/sh\necho \"ChromeDriver {version} (0d8b2d1a7d6b)\"\n").into_bytes()
+    }
+
+    // an index with one build of 119 and one of 120, both served by the test server
+    fn driver_server() -> TestServer {
+        TestServer::start(|r| {
+            let base = format!("http://{}", r.headers["host"]);
+            let build = |version: &str| {
+                serde_json::json!({
+                    "version": version,
+                    "downloads": { "chromedriver": [
+                        { "platform": static_data::DRIVER_PLATFORM, "url": format!("{base}/{version}/chromedriver.zip") }
+                    ] }
+                })
+            };
+            match r.path.as_str() {
+                "/known-good-versions.json" => Reply::ok(
+                    serde_json::json!({ "versions": [build("119.0.6045.105"), build("120.0.6099.71")] }).to_string(),
+                ),
+                "/119.0.6045.105/chromedriver.zip" => Reply::ok(zip(&[("chromedriver-linux64/chromedriver", &fake_driver("119.0.6045.105"))])),
+                "/120.0.6099.71/chromedriver.zip" => Reply::ok(zip(&[("chromedriver-linux64/chromedriver", &fake_driver("120.0.6099.71"))])),
+                _ => Reply::status(404),
+            }
+        })
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[tokio::test]
+    async fn driver_follow_chrome_updates() {
+        let dir = temp_dir("driver-provision");
+        let path = all_path(&dir);
+        fs::create_dir_all(&path.extract_path).unwrap();
+        let server = driver_server();
+        let args = Args::parse_from(["neko_dl", "--driver-index", &server.url("/known-good-versions.json")]);
+
+        // nothing in utils yet
+        assert!(match_chrome(Some("119.0.6045.105".to_string()), &path, &args).await.unwrap());
+        assert_eq!(driver_version(&path.chrome_path).as_deref(), Some("119.0.6045.105"));
+        assert_eq!(fs::read_dir(&path.extract_path).unwrap().count(), 1);
+
+        // same major, nothing is downloaded
+        assert!(match_chrome(Some("119.0.6045.159".to_string()), &path, &args).await.unwrap());
+        assert_eq!(server.requests("/known-good-versions.json").len(), 1);
+
+        // chrome updated
+        assert!(match_chrome(Some("120.0.6099.71".to_string()), &path, &args).await.unwrap());
+        assert_eq!(driver_version(&path.chrome_path).as_deref(), Some("120.0.6099.71"));
+        assert_eq!(server.requests("/120.0.6099.71/chromedriver.zip").len(), 1);
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[tokio::test]
+    async fn the_old_driver_is_kept_when_no_build_match() {
+        let dir = temp_dir("driver-no-build");
+        let path = all_path(&dir);
+        fs::create_dir_all(&path.extract_path).unwrap();
+        let server = driver_server();
+        let args = Args::parse_from(["neko_dl", "--driver-index", &server.url("/known-good-versions.json")]);
+
+        assert!(match_chrome(Some("119.0.6045.105".to_string()), &path, &args).await.unwrap());
+        // no 121 in the index, the 119 driver may still work
+        assert!(match_chrome(Some("121.0.6167.85".to_string()), &path, &args).await.unwrap());
+        assert_eq!(driver_version(&path.chrome_path).as_deref(), Some("119.0.6045.105"));
+
+        // nothing installed and nothing to download
+        fs::remove_file(&path.chrome_path).unwrap();
+        assert!(!match_chrome(Some("121.0.6167.85".to_string()), &path, &args).await.unwrap());
+    }
+
+    #[test]
+    fn exact_version_is_preferred() {
+        assert_eq!(
+            pick_download(&index(), "120.0.6099.71", "linux64"),
+            Some((
+                "120.0.6099.71".to_string(),
+                "https://storage.googleapis.com/chrome-for-testing-public/120.0.6099.71/linux64/chromedriver-linux64.zip".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn latest_build_of_the_same_major_otherwise() {
+        // 120.0.6099.200 has no linux64 driver, 109 is newer than 71 even if it sort before as text
+        let (version, url) = pick_download(&index(), "120.0.6099.129", "linux64").unwrap();
+        assert_eq!(version, "120.0.6099.109");
+        assert!(url.ends_with("/120.0.6099.109/linux64/chromedriver-linux64.zip"), "{url}");
+
+        let (version, _) = pick_download(&index(), "120.0.6099.129", "win64").unwrap();
+        assert_eq!(version, "120.0.6099.200");
+    }
+
+    #[test]
+    fn no_driver_for_another_major() {
+        assert_eq!(pick_download(&index(), "122.0.6261.57", "linux64"), None);
+        // before chrome for testing there is no chromedriver in the index
+        assert_eq!(pick_download(&index(), "114.0.5735.90", "linux64"), None);
+        assert_eq!(pick_download(&serde_json::json!({}), "120.0.6099.71", "linux64"), None);
+    }
+
+    #[test]
+    fn only_the_binary_is_unpacked() {
+        let dir = temp_dir("unpack-driver");
+        let destination = dir.join("chromedriver");
+        let archive = zip(&[
+            ("chromedriver-linux64/LICENSE.chromedriver", b"license"),
+            ("chromedriver-linux64/chromedriver", b"driver binary"),
+        ]);
+
+        unpack_driver(&archive, &destination).unwrap();
+        assert_eq!(fs::read(&destination).unwrap(), b"driver binary");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(any(target_os = "macos", target_os = "linux"))]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&destination).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn an_archive_without_driver_keep_the_old_one() {
+        let dir = temp_dir("unpack-no-driver");
+        let destination = dir.join("chromedriver");
+        fs::write(&destination, b"old driver").unwrap();
+        let archive = zip(&[("chrome-linux64/chrome", b"not the driver")]);
+
+        let e = unpack_driver(&archive, &destination).unwrap_err();
+        assert_eq!(e.to_string(), "no chromedriver in the archive");
+        assert_eq!(fs::read(&destination).unwrap(), b"old driver");
+        assert!(unpack_driver(b"not a zip", &destination).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::mod_file::http_scraper;
+
+// jwplayer().setup({ file: "..." }) or sources: [{ "file": "..." }]
+static JWPLAYER_FILE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"["']?file["']?\s*:\s*["']([^"']+\.(?:m3u8|mp4)[^"']*)["']"#).unwrap());
+static VIDEO_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?is)<(?:video|source)[^>]+src=["']([^"']+)["']"#).unwrap());
+
+// one kind of player page, `extract` only read the html so a saved page is enough to check it
+pub trait EmbedExtractor: Sync {
+    fn name(&self) -> &'static str;
+
+    // iframe hosts this extractor is made for, empty for the generic ones
+    fn hosts(&self) -> &'static [&'static str];
+
+    fn extract(&self, html: &str, embed_url: &str) -> Option<String>;
+
+    // most stream hosts refuse a request without the player as referer
+    fn headers(&self, embed_url: &str) -> Vec<(String, String)> {
+        let Ok(url) = Url::parse(embed_url) else {
+            return vec![];
+        };
+        let origin = url.origin().ascii_serialization();
+        vec![
+            ("Referer".to_string(), format!("{origin}/")),
+            ("Origin".to_string(), origin),
+        ]
+    }
+}
+
+// the player of most neko-sama episodes
+pub struct Jwplayer;
+
+impl EmbedExtractor for Jwplayer {
+    fn name(&self) -> &'static str {
+        "jwplayer"
+    }
+
+    fn hosts(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn extract(&self, html: &str, _embed_url: &str) -> Option<String> {
+        if let Some(c) = JWPLAYER_FILE.captures(html) {
+            return Some(c[1].replace("\\/", "/"));
+        }
+        http_scraper::find_m3u8_in_text(html)
+    }
+}
+
+// fusevideo hide the playlist in a base64 blob and check the referer
+pub struct Fusevideo;
+
+impl EmbedExtractor for Fusevideo {
+    fn name(&self) -> &'static str {
+        "fusevideo"
+    }
+
+    fn hosts(&self) -> &'static [&'static str] {
+        &["fusevideo.net", "fusevideo.io"]
+    }
+
+    // the config is a base64 json, its source has no .m3u8 in the url so the generic search miss it
+    fn extract(&self, html: &str, _embed_url: &str) -> Option<String> {
+        http_scraper::atob_strings(html).iter().find_map(|decoded| {
+            let config = serde_json::from_str::<Value>(decoded).ok()?;
+            config["sources"]
+                .as_array()?
+                .iter()
+                .find_map(|source| source["file"].as_str())
+                .map(|file| file.to_string())
+        })
+    }
+}
+
+// a plain <video> or <source> tag
+pub struct Html5Video;
+
+impl EmbedExtractor for Html5Video {
+    fn name(&self) -> &'static str {
+        "html5 video"
+    }
+
+    fn hosts(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn extract(&self, html: &str, embed_url: &str) -> Option<String> {
+        let src = VIDEO_TAG.captures_iter(html).map(|c| c[1].to_string()).find(|src| {
+            let path = src.split('?').next().unwrap_or_default();
+            path.ends_with(".m3u8") || path.ends_with(".mp4")
+        })?;
+        // src can be relative to the player page
+        Url::parse(embed_url)
+            .and_then(|base| base.join(&src))
+            .map(|url| url.to_string())
+            .ok()
+    }
+}
+
+// host specific extractors first, jwplayer is the default for every other host
+static EXTRACTORS: &[&dyn EmbedExtractor] = &[&Fusevideo, &Jwplayer, &Html5Video];
+
+fn matches(extractor: &dyn EmbedExtractor, host: &str) -> bool {
+    extractor
+        .hosts()
+        .iter()
+        .any(|h| host == *h || host.ends_with(&format!(".{h}")))
+}
+
+// every extractor to try for this player, in order
+pub fn for_url(embed_url: &str) -> Vec<&'static dyn EmbedExtractor> {
+    let host = Url::parse(embed_url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_default();
+    let specific = EXTRACTORS.iter().filter(|e| matches(**e, &host));
+    let generic = EXTRACTORS.iter().filter(|e| e.hosts().is_empty());
+    specific.chain(generic).copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FUSEVIDEO: &str = include_str!("../../tests/fixtures/players/fusevideo.html");
+    const JWPLAYER: &str = include_str!("../../tests/fixtures/players/jwplayer.html");
+    const HTML5: &str = include_str!("../../tests/fixtures/players/html5.html");
+
+    fn names(embed_url: &str) -> Vec<&'static str> {
+        for_url(embed_url).iter().map(|e| e.name()).collect()
+    }
+
+    #[test]
+    fn host_specific_extractors_come_first() {
+        assert_eq!(names("https://fusevideo.io/e/3b9Xk2"), vec!["fusevideo", "jwplayer", "html5 video"]);
+        assert_eq!(names("https://www.fusevideo.net/e/3b9Xk2"), vec!["fusevideo", "jwplayer", "html5 video"]);
+        assert_eq!(names("https://pstream.net/e/Qm2vT8"), vec!["jwplayer", "html5 video"]);
+        assert_eq!(names("https://notfusevideo.io/e/1"), vec!["jwplayer", "html5 video"]);
+    }
+
+    #[test]
+    fn fusevideo_source_is_read_from_its_config() {
+        assert_eq!(
+            Fusevideo.extract(FUSEVIDEO, "https://fusevideo.io/e/3b9Xk2").as_deref(),
+            Some("https://cdn.fusevideo.io/stream/3b9Xk2/index?t=Zx81")
+        );
+        // the url has no .m3u8, only fusevideo find it
+        assert_eq!(Jwplayer.extract(FUSEVIDEO, "https://fusevideo.io/e/3b9Xk2"), None);
+    }
+
+    #[test]
+    fn jwplayer_setup_file_is_unescaped() {
+        assert_eq!(
+            Jwplayer.extract(JWPLAYER, "https://pstream.net/e/Qm2vT8").as_deref(),
+            Some("https://pstream.net/h/Qm2vT8/master.m3u8?e=1700000000")
+        );
+        assert_eq!(Fusevideo.extract(JWPLAYER, "https://pstream.net/e/Qm2vT8"), None);
+    }
+
+    #[test]
+    fn html5_source_is_made_absolute() {
+        assert_eq!(
+            Html5Video.extract(HTML5, "https://video.example/embed/ep2").as_deref(),
+            Some("https://video.example/videos/ep2.mp4?token=a1")
+        );
+        assert_eq!(Html5Video.extract(JWPLAYER, "https://pstream.net/e/Qm2vT8"), None);
+    }
+
+    #[test]
+    fn referer_and_origin_are_the_player_host() {
+        assert_eq!(
+            Fusevideo.headers("https://fusevideo.io/e/3b9Xk2?autoplay=1"),
+            vec![
+                ("Referer".to_string(), "https://fusevideo.io/".to_string()),
+                ("Origin".to_string(), "https://fusevideo.io".to_string()),
+            ]
+        );
+        assert!(Jwplayer.headers("not a url").is_empty());
+    }
+}
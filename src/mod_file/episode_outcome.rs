@@ -0,0 +1,227 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{error, header, info, warn};
+use crate::mod_file::{direct_download, hls_downloader, video_check, web::DownloadContext};
+
+// what one worker send back through the channel once an episode is done
+#[derive(Debug, Clone, Default)]
+pub struct EpisodeOutcome {
+    pub name: String,
+    pub output: PathBuf,
+    pub playlist: PathBuf,
+    pub success: bool,
+    pub skipped: bool,
+    pub exit_code: Option<i32>,
+    pub stderr_tail: Vec<String>,
+    pub output_size: u64,
+    pub duration: Option<f64>,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+    pub verify_error: Option<String>,
+    pub verified: bool,
+    pub attempt: u8,
+    pub log_file: Option<PathBuf>,
+}
+
+impl EpisodeOutcome {
+    pub fn new(output: &str, playlist: &str) -> EpisodeOutcome {
+        EpisodeOutcome {
+            name: Path::new(output)
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or(output)
+                .to_string(),
+            output: PathBuf::from(output),
+            playlist: PathBuf::from(playlist),
+            duration: hls_downloader::read_media_playlist(playlist)
+                .ok()
+                .map(|pl| pl.segments.iter().map(|s| s.duration as f64).sum())
+                // a direct video has no duration in its playlist
+                .filter(|d: &f64| *d > 0.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn already_done(output: &Path) -> EpisodeOutcome {
+        EpisodeOutcome {
+            name: output
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            output: output.to_path_buf(),
+            success: true,
+            skipped: true,
+            output_size: fs::metadata(output).map(|m| m.len()).unwrap_or(0),
+            ..Default::default()
+        }
+    }
+
+    // ffmpeg exit 0 is not enough, the output is probed and compared to the playlist duration
+    pub fn finish(mut self, time: Instant, ctx: &DownloadContext) -> EpisodeOutcome {
+        self.elapsed = time.elapsed();
+        self.attempt = ctx.attempt;
+        self.output_size = fs::metadata(&self.output).map(|m| m.len()).unwrap_or(0);
+        self.check_exit();
+        if self.success {
+            match video_check::verify(&self.output, self.duration, &ctx.ffmpeg) {
+                Ok(probe) => self.verified = probe.is_some(),
+                Err(e) => {
+                    self.success = false;
+                    self.error = Some(format!("verification failed: {e}"));
+                    self.verify_error = Some(e);
+                }
+            }
+        }
+        if !self.success {
+            self.log_file = self.save_log(&ctx.log_path);
+            error!(
+                "{} failed: {}",
+                self.name,
+                self.error.as_deref().unwrap_or_default()
+            );
+        }
+        self
+    }
+
+    // a direct video is copied without ffmpeg, else ffmpeg must exit with 0, no code mean it was killed
+    fn check_exit(&mut self) {
+        let exited = direct_download::is_link(&self.playlist) || self.exit_code == Some(0);
+        self.success = self.error.is_none() && exited && self.output_size > 0;
+        if !self.success && self.error.is_none() {
+            self.error = Some(match self.exit_code {
+                Some(code) if code != 0 => format!("ffmpeg exit with code {code}"),
+                None if !exited => "ffmpeg was killed".to_string(),
+                _ => "output file is empty or missing".to_string(),
+            });
+        }
+    }
+
+    // the journal is the only trace of an unfinished episode, it go away once ffprobe checked the mp4
+    pub fn can_discard_journal(&self) -> bool {
+        self.success && self.verified
+    }
+
+    // network errors already went through the retry layer, only a file that fail verification is downloaded again
+    pub fn need_redownload(&self, max_redownload: u8) -> bool {
+        self.verify_error.is_some() && self.attempt < max_redownload
+    }
+
+    fn save_log(&self, log_path: &Path) -> Option<PathBuf> {
+        fs::create_dir_all(log_path).ok()?;
+        let file = log_path.join(Path::new(&self.name).with_extension("log"));
+        let content = format!(
+            "episode: {}\noutput: {}\nattempt: {}\nerror: {}\nffmpeg exit code: {}\nelapsed: {:.1}s\n\n{}\n",
+            self.name,
+            self.output.display(),
+            self.attempt + 1,
+            self.error.as_deref().unwrap_or_default(),
+            self.exit_code.map(|c| c.to_string()).unwrap_or("none".to_string()),
+            self.elapsed.as_secs_f64(),
+            self.stderr_tail.join("\n")
+        );
+        fs::write(&file, content).ok()?;
+        Some(file)
+    }
+}
+
+pub fn print_summary(outcomes: &[EpisodeOutcome]) {
+    let failed: Vec<_> = outcomes.iter().filter(|o| !o.success).collect();
+    let skipped = outcomes.iter().filter(|o| o.skipped).count();
+    let total_size: u64 = outcomes.iter().map(|o| o.output_size).sum();
+
+    header!(
+        "Summary: {} ok ({} already downloaded), {} failed, {:.2} Go",
+        outcomes.len() - failed.len(),
+        skipped,
+        failed.len(),
+        total_size as f64 / 1024.0 / 1024.0 / 1024.0
+    );
+
+    for outcome in outcomes.iter().filter(|o| o.success && !o.skipped) {
+        info!(
+            "{} {:.0} Mo in {:.0}s{}{}{}",
+            outcome.name,
+            outcome.output_size as f64 / 1024.0 / 1024.0,
+            outcome.elapsed.as_secs_f64(),
+            outcome
+                .duration
+                .map(|d| format!(" ({:.0} min of video)", d / 60.0))
+                .unwrap_or_default(),
+            if outcome.verified { "" } else { " not verified" },
+            if outcome.attempt > 0 {
+                format!(" after {} re-download", outcome.attempt)
+            } else {
+                String::new()
+            }
+        );
+    }
+
+    for outcome in failed {
+        warn!(
+            "{} failed: {}{}",
+            outcome.name,
+            outcome.error.as_deref().unwrap_or_default(),
+            outcome
+                .log_file
+                .as_ref()
+                .map(|f| format!(" (log: {})", f.display()))
+                .unwrap_or_default()
+        );
+        if let Some(last) = outcome.stderr_tail.last() {
+            warn!("  {}", last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(playlist: &str, exit_code: Option<i32>, output_size: u64) -> EpisodeOutcome {
+        let mut outcome = EpisodeOutcome {
+            playlist: PathBuf::from(playlist),
+            exit_code,
+            output_size,
+            ..Default::default()
+        };
+        outcome.check_exit();
+        outcome
+    }
+
+    #[test]
+    fn ffmpeg_must_exit_with_zero() {
+        assert!(outcome("tmp/ep1.m3u8", Some(0), 10).success);
+
+        let failed = outcome("tmp/ep1.m3u8", Some(1), 10);
+        assert!(!failed.success);
+        assert_eq!(failed.error.as_deref(), Some("ffmpeg exit with code 1"));
+
+        // killed by a signal
+        let killed = outcome("tmp/ep1.m3u8", None, 10);
+        assert!(!killed.success);
+        assert_eq!(killed.error.as_deref(), Some("ffmpeg was killed"));
+    }
+
+    #[test]
+    fn a_direct_video_has_no_exit_code() {
+        assert!(outcome("tmp/ep1.link", None, 10).success);
+        assert_eq!(
+            outcome("tmp/ep1.link", None, 0).error.as_deref(),
+            Some("output file is empty or missing")
+        );
+    }
+
+    #[test]
+    fn journal_is_kept_until_the_mp4_is_verified() {
+        let mut done = outcome("tmp/ep1.m3u8", Some(0), 10);
+        // ffprobe missing
+        assert!(!done.can_discard_journal());
+        done.verified = true;
+        assert!(done.can_discard_journal());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    env,
+    error::Error,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use reqwest::Client;
+use tar::Archive;
+use xz2::read::XzDecoder;
+
+use crate::{debug, info, warn};
+use crate::mod_file::{cmd_line_parser::Args, static_data, utils_check::AllPath, web};
+
+// -progress and the options we use are there since ffmpeg 4
+const MIN_MAJOR: u32 = 4;
+
+static VERSION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"ffmpeg version (\S+)").unwrap());
+
+// Ok(true) when path.ffmpeg_path point to a working ffmpeg
+pub async fn ensure_ffmpeg(path: &mut AllPath, args: &Args) -> Result<bool, Box<dyn Error>> {
+    if let Some(ffmpeg) = find_ffmpeg(&path.ffmpeg_path, env::var_os("PATH"), args.debug) {
+        path.ffmpeg_path = ffmpeg;
+        return Ok(true);
+    }
+    if !args.download_ffmpeg {
+        return Ok(false);
+    }
+
+    let Some(url) = static_data::FFMPEG_STATIC_URL else {
+        warn!("No static ffmpeg build for {}, install it with your package manager", env::consts::ARCH);
+        return Ok(false);
+    };
+    info!("Download ffmpeg: {}", url);
+    let archive = web::fetch_bytes(&Client::new(), url).await?;
+    unpack_ffmpeg(&archive, &path.extract_path)?;
+
+    match check_version(&path.ffmpeg_path) {
+        Ok(version) => {
+            info!("ffmpeg {version} installed in {}", path.extract_path.display());
+            Ok(true)
+        }
+        Err(e) => {
+            warn!("Downloaded ffmpeg doesn't work: {e}");
+            Ok(false)
+        }
+    }
+}
+
+// utils/ffmpeg first, then every folder of PATH
+fn find_ffmpeg(local: &Path, path_var: Option<OsString>, debug: bool) -> Option<PathBuf> {
+    let from_path = path_var
+        .map(|p| env::split_paths(&p).map(|dir| dir.join("ffmpeg")).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    for candidate in [local.to_path_buf()].into_iter().chain(from_path) {
+        if !candidate.is_file() {
+            continue;
+        }
+        match check_version(&candidate) {
+            Ok(version) => {
+                if debug {
+                    debug!("ffmpeg {version}: {}", candidate.display());
+                }
+                return Some(candidate);
+            }
+            Err(e) => warn!("Ignore {}: {e}", candidate.display()),
+        }
+    }
+    None
+}
+
+// git builds have no number like "N-113068-g..", they are recent enough
+fn check_version(ffmpeg: &Path) -> Result<String, Box<dyn Error>> {
+    let output = Command::new(ffmpeg).arg("-version").output()?;
+    if !output.status.success() {
+        return Err(format!("exit with {}", output.status).into());
+    }
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_version(stdout: &str) -> Result<String, Box<dyn Error>> {
+    let Some(version) = VERSION.captures(stdout).map(|c| c[1].to_string()) else {
+        return Err("not an ffmpeg binary".into());
+    };
+
+    let major = version
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|n| n.parse::<u32>().ok());
+    match major {
+        Some(major) if major < MIN_MAJOR => Err(format!("version {version} too old, {MIN_MAJOR} or newer needed").into()),
+        _ => Ok(version),
+    }
+}
+
+// the archive contain `ffmpeg-<version>-amd64-static/`, only ffmpeg and ffprobe are kept
+fn unpack_ffmpeg(archive: &[u8], destination: &Path) -> Result<(), Box<dyn Error>> {
+    let mut tar = Archive::new(XzDecoder::new(archive));
+    let mut found = 0;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry
+            .path()?
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default()
+            .to_string();
+        if name != "ffmpeg" && name != "ffprobe" {
+            continue;
+        }
+        let file = destination.join(&name);
+        let tmp = file.with_extension("part");
+        io::copy(&mut entry, &mut File::create(&tmp)?)?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+        fs::rename(&tmp, &file)?;
+        found += 1;
+    }
+    if found == 0 {
+        return Err("no ffmpeg in the archive".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tar::{Builder, Header};
+    use xz2::write::XzEncoder;
+
+    use super::*;
+    use crate::mod_file::test_server::temp_dir;
+
+    // a script answering -version like ffmpeg
+    fn fake_ffmpeg(dir: &Path, version: &str) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let ffmpeg = dir.join("ffmpeg");
+        fs::write(&ffmpeg, format!("#!/bin/sh\necho \"ffmpeg version {version} Copyright (c) 2000-2023\"\n")).unwrap();
+        fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+        ffmpeg
+    }
+
+    fn tar_xz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Builder::new(XzEncoder::new(vec![], 6));
+        for (name, content) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, *content).unwrap();
+        }
+        let mut xz = tar.into_inner().unwrap();
+        xz.flush().unwrap();
+        xz.finish().unwrap()
+    }
+
+    #[test]
+    fn release_and_git_builds_are_accepted() {
+        assert_eq!(parse_version("ffmpeg version 6.1-static https://johnvansickle.com/ffmpeg/").unwrap(), "6.1-static");
+        assert_eq!(parse_version("ffmpeg version 4.4.2-0ubuntu0.22.04.1 Copyright").unwrap(), "4.4.2-0ubuntu0.22.04.1");
+        assert_eq!(parse_version("ffmpeg version N-113068-g0a5a1a5a6e Copyright").unwrap(), "N-113068-g0a5a1a5a6e");
+    }
+
+    #[test]
+    fn old_or_unknown_binaries_are_refused() {
+        let e = parse_version("ffmpeg version 3.4.11 Copyright").unwrap_err();
+        assert_eq!(e.to_string(), "version 3.4.11 too old, 4 or newer needed");
+        assert!(parse_version("avconv version 12.3").is_err());
+        assert!(parse_version("").is_err());
+    }
+
+    #[test]
+    fn utils_come_before_path() {
+        let dir = temp_dir("ffmpeg-order");
+        let local = fake_ffmpeg(&dir.join("utils"), "6.1");
+        let system = fake_ffmpeg(&dir.join("bin"), "5.1.4");
+        let path_var = Some(env::join_paths([dir.join("empty"), dir.join("bin")]).unwrap());
+
+        assert_eq!(find_ffmpeg(&local, path_var.clone(), false), Some(local.clone()));
+
+        // a too old ffmpeg in utils is skipped for the one of PATH
+        fake_ffmpeg(&dir.join("utils"), "3.4");
+        assert_eq!(find_ffmpeg(&local, path_var.clone(), false), Some(system));
+
+        fs::remove_file(&local).unwrap();
+        assert_eq!(find_ffmpeg(&local, None, false), None);
+    }
+
+    #[test]
+    fn only_ffmpeg_and_ffprobe_are_unpacked() {
+        let dir = temp_dir("ffmpeg-unpack");
+        let archive = tar_xz(&[
+            ("ffmpeg-6.1-amd64-static/readme.txt", b"readme"),
+            ("ffmpeg-6.1-amd64-static/ffmpeg", b"ffmpeg binary"),
+            ("ffmpeg-6.1-amd64-static/ffprobe", b"ffprobe binary"),
+            ("ffmpeg-6.1-amd64-static/model/000-PLEASE-README.txt", b"models"),
+        ]);
+
+        unpack_ffmpeg(&archive, &dir).unwrap();
+        assert_eq!(fs::read(dir.join("ffmpeg")).unwrap(), b"ffmpeg binary");
+        assert_eq!(fs::read(dir.join("ffprobe")).unwrap(), b"ffprobe binary");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(fs::metadata(dir.join("ffmpeg")).unwrap().permissions().mode() & 0o777, 0o755);
+    }
+
+    #[test]
+    fn an_archive_without_ffmpeg_is_an_error() {
+        let dir = temp_dir("ffmpeg-unpack-empty");
+
+        let e = unpack_ffmpeg(&tar_xz(&[("readme.txt", b"readme")]), &dir).unwrap_err();
+        assert_eq!(e.to_string(), "no ffmpeg in the archive");
+        assert!(unpack_ffmpeg(b"not an archive", &dir).is_err());
+    }
+}
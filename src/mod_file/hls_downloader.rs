@@ -0,0 +1,481 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    fs::File,
+    io,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use m3u8_rs::{Key, KeyMethod, MediaPlaylist, Playlist};
+use reqwest::{Client, Url};
+use tokio::task::JoinSet;
+
+use crate::{debug, warn};
+use crate::mod_file::{
+    download_progress::EpisodeProgress, episode_outcome::EpisodeOutcome, journal::Journal, rate_limit, web, web::{DownloadContext, FfmpegRun},
+};
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+// m3u8-rs refuse METHOD=NONE without IV (the check is the wrong way round), the tag would be lost
+// and clear segments decrypted with the previous key, a dummy IV is ignored by every reader
+fn keep_method_none(playlist: &[u8]) -> Vec<u8> {
+    String::from_utf8_lossy(playlist)
+        .lines()
+        .map(|line| match line.trim() {
+            "#EXT-X-KEY:METHOD=NONE" => "#EXT-X-KEY:METHOD=NONE,IV=0x0",
+            _ => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .into_bytes()
+}
+
+// rewrite every segment, key and init section uri as absolute, the playlist is saved in tmp/ so relative uri are lost
+pub fn resolve_playlist_uris(playlist: &str, playlist_url: &str) -> Vec<u8> {
+    let (Ok(base), Ok(Playlist::MediaPlaylist(mut pl))) = (
+        Url::parse(playlist_url),
+        m3u8_rs::parse_playlist_res(&keep_method_none(playlist.as_bytes())),
+    ) else {
+        return playlist.as_bytes().to_vec();
+    };
+
+    for segment in &mut pl.segments {
+        if let Ok(uri) = base.join(&segment.uri) {
+            segment.uri = uri.to_string();
+        }
+        if let Some(key) = &mut segment.key {
+            if let Some(uri) = key.uri.as_ref().and_then(|uri| base.join(uri).ok()) {
+                key.uri = Some(uri.to_string());
+            }
+        }
+        if let Some(map) = &mut segment.map {
+            if let Ok(uri) = base.join(&map.uri) {
+                map.uri = uri.to_string();
+            }
+        }
+    }
+
+    let mut out = vec![];
+    match pl.write_to(&mut out) {
+        Ok(_) => out,
+        Err(_) => playlist.as_bytes().to_vec(),
+    }
+}
+
+// headers are kept in the playlist as comments, every player ignore them
+const HEADER_COMMENT: &str = "#NEKO-DL-HEADER:";
+
+pub fn with_headers(playlist: &[u8], headers: &[(String, String)]) -> Vec<u8> {
+    // #EXTM3U has to stay the first line
+    let split = playlist.iter().position(|b| *b == b'\n').map(|i| i + 1).unwrap_or(playlist.len());
+    let mut out = playlist[..split].to_vec();
+    for (key, value) in headers {
+        out.extend_from_slice(format!("{HEADER_COMMENT}{key}: {value}\n").as_bytes());
+    }
+    out.extend_from_slice(&playlist[split..]);
+    out
+}
+
+pub fn playlist_headers(path: &str) -> Vec<(String, String)> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix(HEADER_COMMENT))
+        .filter_map(|header| header.split_once(": "))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+// init sections and byte ranges aren't fetched by the native downloader, ffmpeg read them fine
+fn unsupported_tag(pl: &MediaPlaylist) -> Option<&'static str> {
+    if pl.segments.iter().any(|s| s.map.is_some()) {
+        Some("EXT-X-MAP")
+    } else if pl.segments.iter().any(|s| s.byte_range.is_some()) {
+        Some("EXT-X-BYTERANGE")
+    } else {
+        None
+    }
+}
+
+pub fn download_native(path: &str, name: &str, ctx: &DownloadContext) -> EpisodeOutcome {
+    if let Some(tag) = read_media_playlist(path).ok().as_ref().and_then(unsupported_tag) {
+        if rate_limit::is_enabled() {
+            warn!("{name} use {tag}, not handled by the native downloader, download it with ffmpeg without rate limit");
+        } else {
+            warn!("{name} use {tag}, not handled by the native downloader, download it with ffmpeg");
+        }
+        return web::download_build_video(path, name, ctx);
+    }
+
+    let time = Instant::now();
+    let mut outcome = EpisodeOutcome::new(name, path);
+    let ts_path = PathBuf::from(name).with_extension("ts");
+    let journal_dir = Journal::dir(&ctx.resume_path, name);
+
+    if ctx.debug {
+        debug!("save path: {} output name: {}", path, name);
+    }
+
+    let progress = ctx.progress.episode(&outcome.name, 0);
+    let fetched = ctx.handle.block_on(fetch_all_segments(path, name, &journal_dir, &progress, ctx));
+    progress.done();
+    match fetched.and_then(|segments| concat_segments(&journal_dir, segments, &ts_path)) {
+        Ok(_) => match remux(&ts_path, name, ctx) {
+            Ok(run) => {
+                outcome.exit_code = run.exit_code;
+                outcome.stderr_tail = run.stderr_tail;
+            }
+            Err(e) => outcome.error = Some(format!("Can't start ffmpeg: {e}")),
+        },
+        Err(e) => outcome.error = Some(format!("Can't download segments: {e}")),
+    }
+
+    let _ = fs::remove_file(&ts_path);
+
+    let outcome = outcome.finish(time, ctx);
+    if outcome.can_discard_journal() {
+        Journal::discard(&journal_dir);
+    }
+    outcome
+}
+
+pub fn read_media_playlist(path: &str) -> Result<MediaPlaylist, Box<dyn Error>> {
+    let content = keep_method_none(&fs::read(path)?);
+    match m3u8_rs::parse_playlist_res(&content) {
+        Ok(Playlist::MediaPlaylist(pl)) => Ok(pl),
+        Ok(Playlist::MasterPlaylist(_)) => Err(format!("{path} is a master playlist").into()),
+        Err(e) => Err(format!("Error parse m3u8 {path}: {:?}", e).into()),
+    }
+}
+
+async fn fetch_all_segments(path: &str, name: &str, journal_dir: &Path, progress: &EpisodeProgress, ctx: &DownloadContext) -> Result<usize, Box<dyn Error>> {
+    let pl = read_media_playlist(path)?;
+    let headers = playlist_headers(path);
+    let total = pl.segments.len();
+    let mut journal = Journal::load_or_new(journal_dir, name, total)?;
+
+    // resumed segments count in the episode progress too
+    let mut downloaded: u64 = (0..total)
+        .filter(|index| journal.is_done(journal_dir, *index))
+        .filter_map(|index| fs::metadata(Journal::segment_path(journal_dir, index)).ok())
+        .map(|m| m.len())
+        .sum();
+    progress.set_length(total as u64);
+    progress.update(journal.done.len() as u64, downloaded);
+
+    if ctx.debug && !journal.done.is_empty() {
+        debug!("resume {} from {}/{} segments", name, journal.done.len(), total);
+    }
+
+    // a key tag apply to every following segment until the next one
+    let mut segment_keys = Vec::with_capacity(total);
+    let mut current_key: Option<Key> = None;
+    for segment in &pl.segments {
+        if segment.key.is_some() {
+            current_key = segment.key.clone();
+        }
+        segment_keys.push(current_key.clone());
+    }
+
+    // every segment wait a permit of the shared budget, results come back in any order
+    let mut tasks = JoinSet::new();
+    for (index, segment) in pl.segments.iter().enumerate() {
+        if journal.is_done(journal_dir, index) {
+            continue;
+        }
+        let client = ctx.client.clone();
+        let connections = ctx.connections.clone();
+        let uri = segment.uri.clone();
+        let headers = headers.clone();
+        tasks.spawn(async move {
+            let _permit = connections.acquire_owned().await.map_err(|e| e.to_string())?;
+            web::fetch_bytes_with_headers(&client, &uri, &headers)
+                .await
+                .map(|bytes| (index, bytes))
+        });
+    }
+
+    let mut key_cache: HashMap<String, Vec<u8>> = HashMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (index, mut bytes) = joined??;
+        if let Some(key) = &segment_keys[index] {
+            let sequence = pl.media_sequence + index as u64;
+            bytes = decrypt_segment(bytes, key, sequence, &mut key_cache, &ctx.client, &headers).await?;
+        }
+        fs::write(Journal::segment_path(journal_dir, index), &bytes)?;
+        journal.mark_done(journal_dir, index)?;
+        downloaded += bytes.len() as u64;
+        progress.update(journal.done.len() as u64, downloaded);
+        if ctx.debug {
+            debug!("segment {}/{} {} bytes", index + 1, total, bytes.len());
+        }
+    }
+
+    Ok(total)
+}
+
+fn concat_segments(journal_dir: &Path, segments: usize, ts_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut out = File::create(ts_path)?;
+    for index in 0..segments {
+        out.write_all(&fs::read(Journal::segment_path(journal_dir, index))?)?;
+    }
+    Ok(())
+}
+
+async fn decrypt_segment(bytes: Vec<u8>, key: &Key, sequence: u64, key_cache: &mut HashMap<String, Vec<u8>>, client: &Client, headers: &[(String, String)]) -> Result<Vec<u8>, Box<dyn Error>> {
+    match &key.method {
+        KeyMethod::None => Ok(bytes),
+        KeyMethod::AES128 => {
+            let Some(uri) = &key.uri else {
+                return Err("EXT-X-KEY AES-128 without URI".into());
+            };
+
+            if !key_cache.contains_key(uri) {
+                let key_bytes = web::fetch_bytes_with_headers(client, uri, headers).await?;
+                if key_bytes.len() != 16 {
+                    return Err(format!("key {uri} is {} bytes, 16 expected", key_bytes.len()).into());
+                }
+                key_cache.insert(uri.clone(), key_bytes);
+            }
+
+            let iv = match &key.iv {
+                Some(iv) => parse_iv(iv)?,
+                None => (sequence as u128).to_be_bytes(),
+            };
+
+            Aes128CbcDec::new_from_slices(&key_cache[uri], &iv)
+                .map_err(|e| format!("aes init: {e}"))?
+                .decrypt_padded_vec_mut::<Pkcs7>(&bytes)
+                .map_err(|e| format!("aes decrypt: {e}").into())
+        }
+        method => Err(format!("unsupported EXT-X-KEY METHOD={:?}", method).into()),
+    }
+}
+
+fn parse_iv(iv: &str) -> Result<[u8; 16], Box<dyn Error>> {
+    let hex = iv.trim_start_matches("0x").trim_start_matches("0X");
+    let value = u128::from_str_radix(hex, 16).map_err(|e| format!("bad IV {iv}: {e}"))?;
+    Ok(value.to_be_bytes())
+}
+
+fn remux(ts_path: &Path, name: &str, ctx: &DownloadContext) -> io::Result<FfmpegRun> {
+    let mut process = web::ffmpeg_command(&ctx.ffmpeg);
+    process.args([
+        "-y",
+        "-i",
+        ts_path.to_str().unwrap(),
+        "-bsf:a",
+        "aac_adtstoasc",
+        "-c:v",
+        "copy",
+        "-c:a",
+        "copy",
+        name,
+    ]);
+    web::run_ffmpeg(process, ctx.debug.then_some(&ctx.progress), |_, _| {})
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use aes::cipher::BlockEncryptMut;
+    use tokio::{runtime::Handle, sync::Semaphore};
+
+    use super::*;
+    use crate::mod_file::{
+        download_progress::DownloadProgress,
+        test_server::{temp_dir, Reply, TestServer},
+    };
+
+    type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+    const KEY: [u8; 16] = [0x11; 16];
+
+    fn context(dir: &Path) -> DownloadContext {
+        DownloadContext {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            resume_path: dir.join("resume"),
+            log_path: dir.join("logs"),
+            client: Client::new(),
+            handle: Handle::current(),
+            connections: Arc::new(Semaphore::new(4)),
+            progress: DownloadProgress::new(1),
+            attempt: 0,
+            debug: false,
+        }
+    }
+
+    fn encrypt(plain: &[u8], iv: u128) -> Vec<u8> {
+        Aes128CbcEnc::new_from_slices(&KEY, &iv.to_be_bytes())
+            .unwrap()
+            .encrypt_padded_vec_mut::<Pkcs7>(plain)
+    }
+
+    // download the playlist like html_parser, then every segment like download_native without the remux
+    async fn download(server: &TestServer, playlist_path: &str, dir: &Path, headers: &[(String, String)]) -> Vec<u8> {
+        let playlist_url = server.url(playlist_path);
+        let playlist = web::read_text(web::web_request_with_headers(&Client::new(), &playlist_url, &[]).await.unwrap())
+            .await
+            .unwrap();
+        let path = dir.join("episode.m3u8");
+        fs::write(&path, with_headers(&resolve_playlist_uris(&playlist, &playlist_url), headers)).unwrap();
+
+        let ctx = context(dir);
+        let name = dir.join("episode.mp4");
+        let (path, name) = (path.to_str().unwrap(), name.to_str().unwrap());
+        let journal_dir = Journal::dir(&ctx.resume_path, name);
+        let progress = ctx.progress.episode("episode", 0);
+        let segments = fetch_all_segments(path, name, &journal_dir, &progress, &ctx).await.unwrap();
+        progress.done();
+
+        let ts_path = dir.join("episode.ts");
+        concat_segments(&journal_dir, segments, &ts_path).unwrap();
+        fs::read(ts_path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn relative_uris_are_resolved_and_segments_concatenated() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/video/index.m3u8" => Reply::ok(include_str!("../../tests/fixtures/hls/relative.m3u8")),
+            "/video/seg0.ts" => Reply::ok("first-"),
+            "/shared/seg1.ts" => Reply::ok("second-"),
+            "/cdn/seg2.ts" => Reply::ok("third"),
+            _ => Reply::status(404),
+        });
+        let dir = temp_dir("hls-relative");
+
+        let referer = vec![("Referer".to_string(), "https://player.example/".to_string())];
+
+        let output = download(&server, "/video/index.m3u8", &dir, &referer).await;
+
+        let saved = read_media_playlist(dir.join("episode.m3u8").to_str().unwrap()).unwrap();
+        let uris: Vec<&str> = saved.segments.iter().map(|s| s.uri.as_str()).collect();
+        assert_eq!(
+            uris,
+            [server.url("/video/seg0.ts"), server.url("/shared/seg1.ts"), server.url("/cdn/seg2.ts")]
+        );
+        assert_eq!(saved.segments[2].duration, 4.5);
+        assert_eq!(output, b"first-second-third");
+        // headers saved with the playlist go with every segment
+        let seg = &server.requests("/cdn/seg2.ts")[0];
+        assert_eq!(seg.headers.get("referer").map(|r| r.as_str()), Some("https://player.example/"));
+    }
+
+    #[tokio::test]
+    async fn aes_segments_are_decrypted_with_explicit_and_sequence_iv() {
+        let plain: Vec<Vec<u8>> = (0..4).map(|i| format!("segment {i} payload").into_bytes()).collect();
+        let explicit_iv = 0x000102030405060708090a0b0c0d0e0f;
+        // media sequence 7, the third segment has no IV so its sequence number 9 is used
+        let served = [
+            encrypt(&plain[0], explicit_iv),
+            encrypt(&plain[1], explicit_iv),
+            encrypt(&plain[2], 9),
+            plain[3].clone(),
+        ];
+        let server = TestServer::start(move |r| match r.path.as_str() {
+            "/enc/index.m3u8" => Reply::ok(include_str!("../../tests/fixtures/hls/encrypted.m3u8")),
+            "/enc/key.bin" => Reply::ok(KEY.to_vec()),
+            "/enc/seg0.ts" => Reply::ok(served[0].clone()),
+            "/enc/seg1.ts" => Reply::ok(served[1].clone()),
+            "/enc/seg2.ts" => Reply::ok(served[2].clone()),
+            "/enc/seg3.ts" => Reply::ok(served[3].clone()),
+            _ => Reply::status(404),
+        });
+        let dir = temp_dir("hls-encrypted");
+
+        let output = download(&server, "/enc/index.m3u8", &dir, &[]).await;
+
+        // METHOD=NONE stop the decryption, the last segment come back as served
+        assert_eq!(output, plain.concat());
+        assert_eq!(server.requests("/enc/key.bin").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_key_of_the_wrong_size_is_refused() {
+        let server = TestServer::start(|_| Reply::ok(vec![0u8; 15]));
+        let key = Key {
+            method: KeyMethod::AES128,
+            uri: Some(server.url("/key.bin")),
+            ..Default::default()
+        };
+
+        let result = decrypt_segment(vec![0; 16], &key, 0, &mut HashMap::new(), &Client::new(), &[]).await;
+
+        assert!(result.unwrap_err().to_string().contains("15 bytes"));
+    }
+
+    #[test]
+    fn method_none_survive_the_parser() {
+        let playlist = include_str!("../../tests/fixtures/hls/encrypted.m3u8");
+
+        let resolved = resolve_playlist_uris(playlist, "https://cdn.example/enc/index.m3u8");
+
+        let Ok(Playlist::MediaPlaylist(pl)) = m3u8_rs::parse_playlist_res(&keep_method_none(&resolved)) else {
+            panic!("not a media playlist");
+        };
+        assert_eq!(pl.segments[0].key.as_ref().unwrap().uri.as_deref(), Some("https://cdn.example/enc/key.bin"));
+        assert_eq!(pl.segments[3].key.as_ref().map(|k| &k.method), Some(&KeyMethod::None));
+    }
+
+    #[test]
+    fn iv_is_read_as_hex_with_or_without_prefix() {
+        let expected: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        assert_eq!(parse_iv("0x000102030405060708090a0b0c0d0e0f").unwrap(), expected);
+        assert_eq!(parse_iv("0X000102030405060708090A0B0C0D0E0F").unwrap(), expected);
+        assert_eq!(parse_iv("2a").unwrap(), 42u128.to_be_bytes());
+        assert!(parse_iv("0xnothex").is_err());
+    }
+
+    #[test]
+    fn headers_stay_after_the_first_line() {
+        let headers = vec![("Referer".to_string(), "https://player.example/".to_string())];
+        let dir = temp_dir("hls-headers");
+        let path = dir.join("episode.m3u8");
+
+        fs::write(&path, with_headers(b"#EXTM3U\n#EXT-X-ENDLIST\n", &headers)).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("#EXTM3U\n#NEKO-DL-HEADER:Referer: "));
+        assert_eq!(playlist_headers(path.to_str().unwrap()), headers);
+    }
+
+    #[test]
+    fn init_section_is_resolved_and_left_to_ffmpeg() {
+        let playlist = include_str!("../../tests/fixtures/hls/fmp4.m3u8");
+
+        let resolved = resolve_playlist_uris(playlist, "https://cdn.example/fmp4/index.m3u8");
+
+        let Ok(Playlist::MediaPlaylist(pl)) = m3u8_rs::parse_playlist_res(&resolved) else {
+            panic!("not a media playlist");
+        };
+        assert_eq!(pl.segments[0].map.as_ref().unwrap().uri, "https://cdn.example/fmp4/init.mp4");
+        assert_eq!(pl.segments[1].uri, "https://cdn.example/fmp4/seg1.m4s");
+        assert_eq!(unsupported_tag(&pl), Some("EXT-X-MAP"));
+    }
+
+    #[test]
+    fn byte_ranges_are_kept_and_left_to_ffmpeg() {
+        let playlist = include_str!("../../tests/fixtures/hls/byterange.m3u8");
+
+        let resolved = resolve_playlist_uris(playlist, "https://cdn.example/range/index.m3u8");
+
+        let Ok(Playlist::MediaPlaylist(pl)) = m3u8_rs::parse_playlist_res(&resolved) else {
+            panic!("not a media playlist");
+        };
+        assert_eq!(pl.segments[1].uri, "https://cdn.example/range/video.ts");
+        let range = pl.segments[1].byte_range.as_ref().unwrap();
+        assert_eq!((range.length, range.offset), (82112, None));
+        assert_eq!(unsupported_tag(&pl), Some("EXT-X-BYTERANGE"));
+
+        let Ok(Playlist::MediaPlaylist(plain)) = m3u8_rs::parse_playlist_res(include_bytes!("../../tests/fixtures/hls/relative.m3u8")) else {
+            panic!("not a media playlist");
+        };
+        assert_eq!(unsupported_tag(&plain), None);
+    }
+}
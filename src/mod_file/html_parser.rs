@@ -1,46 +1,60 @@
-use std::{error::Error, fs::File, io, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    error::Error,
+    fmt::{Display, Formatter},
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
+use futures::future::join_all;
 use m3u8_rs::Playlist;
-use reqwest::{Client, StatusCode};
-use thirtyfour::{By, WebDriver, WebElement};
+use reqwest::{Client, StatusCode, Url};
+use thirtyfour::{error::WebDriverError, By, WebDriver, WebElement};
 
 use crate::{debug, error, info, warn};
 use crate::mod_file::{
-    cmd_line_parser::Args, static_data::BASE_URL, utils_check::AllPath, utils_data, web,
+    cmd_line_parser::{Args, Resolver}, direct_download, driver_pool::{is_alive, DriverPool},
+    embed_extractor::{self, EmbedExtractor, Jwplayer}, hls_downloader, network_capture::{self, SeenStream, StreamKind}, quality, provider::Provider,
+    process_part1::{Found, Resolved}, utils_check::AllPath, utils_data, web,
 };
 
-pub async fn recursive_find_url(driver: &WebDriver, _url_test: &str, args: &Args, client: &Client, path: &AllPath) -> Result<(u16, u16), Box<dyn Error>> {
+pub async fn recursive_find_url(pool: &mut DriverPool, _url_test: &str, args: &Args, client: &Client, path: &AllPath, provider: &dyn Provider, found: &Found) -> Result<(u16, u16), Box<dyn Error>> {
     let mut all_l = vec![];
+    let page = provider.browser();
+    let driver = pool.first().clone();
 
     // direct url
-    if _url_test.contains("/episode/") {
+    if provider.is_episode_url(_url_test) {
         driver.goto(_url_test).await?;
-        all_l.push(_url_test.replace(BASE_URL, ""));
-        let video_url = enter_iframe_wait_jwplayer(&driver, args, all_l, client, path).await?;
+        all_l.push(_url_test.to_string());
+        let video_url = enter_iframe_wait_jwplayer(pool, args, all_l, client, path, provider, found).await?;
         return Ok(video_url);
     }
 
     // check next page 
-    let n = driver.find_all(By::ClassName("animeps-next-page")).await?;
+    let n = driver.find_all(By::ClassName(page.next_page_class)).await?;
 
     // only one page
     if n.len() == 0 {
-        all_l.extend(get_all_link_base_href(&driver, args).await?);
+        all_l.extend(get_all_link_base_href(&driver, args, provider).await?);
     }
 
     // iter over all page possible
-    let page_return = next_page(&driver, args, &n).await?;
+    let page_return = next_page(&driver, args, &n, provider).await?;
     all_l.extend(page_return);
 
-    let video_url = enter_iframe_wait_jwplayer(&driver, args, all_l, client, path).await?;
+    let video_url = enter_iframe_wait_jwplayer(pool, args, all_l, client, path, provider, found).await?;
     Ok(video_url)
 }
 
-async fn next_page(driver: &WebDriver, args: &Args, n: &Vec<WebElement>) -> Result<Vec<String>, Box<dyn Error>> {
+async fn next_page(driver: &WebDriver, args: &Args, n: &Vec<WebElement>, provider: &dyn Provider) -> Result<Vec<String>, Box<dyn Error>> {
     let mut all_links = vec![];
+    let next_page_class = provider.browser().next_page_class;
     while n.len() != 0 {
-        all_links.extend(get_all_link_base_href(&driver, args).await?);
-        let n = driver.find_all(By::ClassName("animeps-next-page")).await?;
+        all_links.extend(get_all_link_base_href(&driver, args, provider).await?);
+        let n = driver.find_all(By::ClassName(next_page_class)).await?;
         if !n
             .first()
             .expect("first")
@@ -52,7 +66,7 @@ async fn next_page(driver: &WebDriver, args: &Args, n: &Vec<WebElement>) -> Resu
             info!("Next page");
             driver
                 .execute(
-                    r#"document.querySelector('.animeps-next-page').click();"#,
+                    &format!("document.querySelector('.{next_page_class}').click();"),
                     vec![],
                 )
                 .await?;
@@ -64,11 +78,9 @@ async fn next_page(driver: &WebDriver, args: &Args, n: &Vec<WebElement>) -> Resu
     Ok(all_links)
 }
 
-pub async fn get_base_name_direct_url(driver: &WebDriver) -> String {
+pub async fn get_base_name_direct_url(driver: &WebDriver, provider: &dyn Provider) -> String {
     let class = driver
-        .find(By::XPath(
-            r#"//*[@id="watch"]/div/div[4]/div[1]/div/div/h2/a"#,
-        ))
+        .find(By::XPath(provider.browser().series_xpath))
         .await
         .expect("Can't get real name direct url");
 
@@ -79,12 +91,15 @@ pub async fn get_base_name_direct_url(driver: &WebDriver) -> String {
     path
 }
 
-async fn get_all_link_base_href(driver: &WebDriver, args: &Args) -> Result<Vec<String>, Box<dyn Error>> {
+async fn get_all_link_base_href(driver: &WebDriver, args: &Args, provider: &dyn Provider) -> Result<Vec<String>, Box<dyn Error>> {
     let mut url_found = vec![];
-    let mut play_class = driver.find_all(By::ClassName("play")).await?;
+    let mut play_class = vec![];
 
-    if play_class.len() == 0 {
-        play_class = driver.find_all(By::ClassName("text-left")).await?;
+    for class in provider.browser().episode_link_classes {
+        play_class = driver.find_all(By::ClassName(*class)).await?;
+        if play_class.len() != 0 {
+            break;
+        }
     }
 
     for x in play_class {
@@ -98,156 +113,439 @@ async fn get_all_link_base_href(driver: &WebDriver, args: &Args) -> Result<Vec<S
     Ok(url_found)
 }
 
-async fn enter_iframe_wait_jwplayer(driver: &WebDriver, args: &Args, all_l: Vec<String>, client: &Client, path: &AllPath) -> Result<(u16, u16), Box<dyn Error>> {
-    let mut nb_found = 0u16;
-    let mut nb_error = 0u16;
-
-    for fuse_iframe in all_l {
-        let url = format!("{BASE_URL}{fuse_iframe}");
-        driver.handle.goto(&url).await?;
-
-        let url = driver.handle.find(By::Id("un_episode")).await?;
-        // force wait after iframe update jwplayer in html
-        match url.handle.clone().enter_frame(0).await {
-            Ok(_) => {
-                loop {
-                    match driver.handle.find(By::Id("main-player")).await {
-                        Ok(e) => {
-                            if let Ok(a) = e.attr("class").await {
-                                if let Some(a) = a {
-                                    if a.contains("jwplayer") {
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            continue;
+// why an episode page gave no playlist
+#[derive(Debug)]
+pub enum ResolveFailure {
+    IframeMissing,
+    PlayerNotLoaded(u64),
+    ScriptError(String),
+    PlaylistNotFound(String),
+    UnsupportedPlayer(String),
+    NoStreamSeen(u64),
+    // the webdriver command itself failed, the session may be dead
+    Browser(String),
+}
+
+impl Display for ResolveFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveFailure::IframeMissing => write!(f, "player iframe missing (episode removed ?)"),
+            ResolveFailure::PlayerNotLoaded(s) => write!(f, "player not loaded after {s}s (other player or captcha ?)"),
+            ResolveFailure::ScriptError(e) => write!(f, "player script error: {e}"),
+            ResolveFailure::PlaylistNotFound(e) => write!(f, "playlist not found: {e}"),
+            ResolveFailure::UnsupportedPlayer(url) => write!(f, "no extractor found a stream in player {url}"),
+            ResolveFailure::NoStreamSeen(s) => write!(f, "no playlist or video requested by the page after {s}s"),
+            ResolveFailure::Browser(e) => write!(f, "browser error: {e}"),
+        }
+    }
+}
+
+impl From<WebDriverError> for ResolveFailure {
+    fn from(e: WebDriverError) -> Self {
+        ResolveFailure::Browser(e.to_string())
+    }
+}
+
+// every session take the next episode in the queue until it's empty, each playlist is handed to the downloads once saved
+pub async fn enter_iframe_wait_jwplayer(pool: &mut DriverPool, args: &Args, all_l: Vec<String>, client: &Client, path: &AllPath, provider: &dyn Provider, found: &Found) -> Result<(u16, u16), Box<dyn Error>> {
+    pool.grow(all_l.len().min(args.sessions.max(1) as usize), args).await;
+
+    let total = all_l.len();
+    let queue = RefCell::new(VecDeque::from(all_l));
+    let failures = RefCell::new(vec![]);
+    let (driver_url, prefs, sessions) = pool.parts();
+
+    let workers = sessions.iter_mut().map(|session| {
+        let queue = &queue;
+        let failures = &failures;
+        async move {
+            let mut nb_found = 0u16;
+            loop {
+                let next = queue.borrow_mut().pop_front();
+                let Some(link) = next else {
+                    break;
+                };
+
+                let mut result = resolve_episode(session, &link, args, client, path, provider).await;
+
+                if let Err(ResolveFailure::Browser(e)) = &result {
+                    if !is_alive(session).await {
+                        warn!("Browser session lost on {link} ({e}), restart it");
+                        if let Err(e) = DriverPool::restart(driver_url, prefs, session, args).await {
+                            // the other sessions take what is left
+                            error!("Can't restart browser session: {e}");
+                            queue.borrow_mut().push_front(link);
+                            break;
                         }
+                        result = resolve_episode(session, &link, args, client, path, provider).await;
+                    }
+                }
+
+                match result {
+                    Ok(file) => {
+                        nb_found += 1;
+                        // the download start now, the next episodes are resolved meanwhile
+                        let _ = found.send(Resolved::Playlist(file));
+                    }
+                    Err(failure) => {
+                        error!("{link}: {failure}");
+                        failures.borrow_mut().push((link, failure));
                     }
                 }
-                let (found, error) =
-                    find_and_get_m3u8(nb_found, nb_error, &driver, &path, &client, &args).await?;
-                nb_found = found;
-                nb_error = error;
             }
-            Err(_) => {}
+            nb_found
+        }
+    });
+
+    let nb_found: u16 = join_all(workers).await.into_iter().sum();
+    let failures = failures.into_inner();
+    let mut nb_error = failures.len() as u16;
+
+    if !failures.is_empty() {
+        warn!("{} episode(s) without playlist:", failures.len());
+        for (link, failure) in &failures {
+            warn!("  {link}: {failure}");
         }
-        driver.handle.enter_parent_frame().await?;
     }
-    // utils_data::kill_process()?;
+
+    // every session died, what is left is missing
+    let left = queue.borrow().len();
+    if left > 0 {
+        error!("{left}/{total} episode(s) not resolved, no browser session left");
+        nb_error += left as u16;
+    }
+
     Ok((nb_found, nb_error))
 }
 
-async fn find_and_get_m3u8(mut nb_found: u16, mut nb_error: u16, driver: &WebDriver, path: &AllPath, client: &Client, args: &Args) -> Result<(u16, u16), Box<dyn Error>> {
+async fn resolve_episode(driver: &WebDriver, link: &str, args: &Args, client: &Client, path: &AllPath, provider: &dyn Provider) -> Result<PathBuf, ResolveFailure> {
+    let url = provider.absolute_url(link);
+    if args.resolver != Resolver::Jwplayer {
+        // forget what the previous episode requested
+        network_capture::stream_urls(driver).await?;
+    }
+    driver.handle.goto(&url).await?;
+
+    match args.resolver {
+        Resolver::Jwplayer => resolve_from_player(driver, args, client, path, provider).await,
+        Resolver::Network => resolve_from_network(driver, args, client, path, provider).await,
+        // the requests made while waiting jwplayer are still in the log
+        Resolver::Auto => match resolve_from_player(driver, args, client, path, provider).await {
+            Err(ResolveFailure::IframeMissing)
+            | Err(ResolveFailure::PlayerNotLoaded(_))
+            | Err(ResolveFailure::ScriptError(_))
+            | Err(ResolveFailure::UnsupportedPlayer(_)) => {
+                resolve_from_network(driver, args, client, path, provider).await
+            }
+            result => result,
+        },
+    }
+}
+
+// every extractor read the player page first, jwplayer is only waited and asked when none found the stream
+async fn resolve_from_player(driver: &WebDriver, args: &Args, client: &Client, path: &AllPath, provider: &dyn Provider) -> Result<PathBuf, ResolveFailure> {
+    let page = provider.browser();
+    let iframe = match driver.handle.find(By::Id(page.player_frame_id)).await {
+        Ok(iframe) => iframe,
+        Err(WebDriverError::NoSuchElement(_)) => return Err(ResolveFailure::IframeMissing),
+        Err(e) => return Err(e.into()),
+    };
+    let embed_url = iframe.attr("src").await?.unwrap_or_default();
+    let extractors = embed_extractor::for_url(&embed_url);
+    if args.debug {
+        debug!(
+            "player {embed_url}: {}",
+            extractors.iter().map(|e| e.name()).collect::<Vec<_>>().join(", ")
+        );
+    }
+    match iframe.handle.clone().enter_frame(0).await {
+        Ok(_) => {}
+        Err(WebDriverError::NoSuchFrame(_)) => return Err(ResolveFailure::IframeMissing),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut result = Err(ResolveFailure::UnsupportedPlayer(embed_url.clone()));
+    // the page as loaded first, waiting for jwplayer would cost --player-timeout to every other player
+    let html = driver.source().await?;
+    for extractor in &extractors {
+        if let Some(url) = extractor.extract(&html, &embed_url) {
+            let stream = SeenStream::from_url(&url, &extractor.headers(&embed_url));
+            result = save_stream(driver, &stream, path, client, args, provider).await;
+            if result.is_ok() {
+                break;
+            }
+        }
+    }
+    if result.is_err() && extractors.iter().any(|e| e.name() == Jwplayer.name()) {
+        result = match wait_jwplayer(driver, page.player_id, args.player_timeout).await {
+            Ok(()) => find_and_get_m3u8(driver, path, client, args, provider, &Jwplayer.headers(&embed_url)).await,
+            Err(e) => Err(e),
+        };
+    }
+    driver.handle.enter_parent_frame().await?;
+    result
+}
+
+// whatever the player is, it has to request a playlist or a video
+async fn resolve_from_network(driver: &WebDriver, args: &Args, client: &Client, path: &AllPath, provider: &dyn Provider) -> Result<PathBuf, ResolveFailure> {
+    let start = Instant::now();
+    let mut urls = vec![];
+    while start.elapsed() < Duration::from_secs(args.player_timeout) {
+        let seen = urls.len();
+        urls.extend(network_capture::stream_urls(driver).await?);
+        // the master playlist come first, give the player a moment for the others
+        if seen > 0 && seen == urls.len() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    if args.debug {
+        debug!("streams seen: {:?}", urls);
+    }
+
+    let Some(stream) = network_capture::pick_stream(client, &urls).await else {
+        return Err(ResolveFailure::NoStreamSeen(args.player_timeout));
+    };
+    save_stream(driver, &stream, path, client, args, provider).await
+}
+
+// the episode name come from the page, the file is named after it
+async fn save_stream(driver: &WebDriver, stream: &SeenStream, path: &AllPath, client: &Client, args: &Args, provider: &dyn Provider) -> Result<PathBuf, ResolveFailure> {
     let name = utils_data::edit_for_windows_compatibility(
-        &driver.title().await?.replace(" - Neko Sama", ""),
+        &provider.clean_title(&driver.title().await?),
     );
-    match driver
-        .handle
-        .execute(r#"return jwplayer().getPlaylistItem();"#, vec![])
+    let file_name = name.trim().replace(":", "").replace(" ", "_");
+
+    info!("Get stream for: {}", name);
+    save_stream_file(stream, &file_name, &path.tmp_dl, client, args)
         .await
-    {
-        Ok(script) => {
-            info!("Get m3u8 for: {}", name);
-            match script.json()["file"].as_str() {
-                None => {
-                    error!("can't exec js for {name}: {:?}", script)
-                }
-                Some(url) => {
-                    download_and_save_m3u8(
-                        url,
-                        &name.trim().replace(":", "").replace(" ", "_"),
-                        &path.tmp_dl,
-                        &client,
-                        args,
-                    )
-                        .await?;
-
-                    nb_found += 1;
+        .map_err(|e| ResolveFailure::PlaylistNotFound(e.to_string()))
+}
+
+// a playlist is fetched now, a plain video is only noted and downloaded as a file later
+pub async fn save_stream_file(stream: &SeenStream, file_name: &str, tmp_dl: &PathBuf, client: &Client, args: &Args) -> Result<PathBuf, Box<dyn Error>> {
+    match stream.kind {
+        StreamKind::Playlist => download_and_save_m3u8(&stream.url, file_name, tmp_dl, client, args, &stream.headers).await,
+        StreamKind::Video => direct_download::save_link(&stream.url, file_name, tmp_dl, &stream.headers),
+    }
+}
+
+// jwplayer add its class to the player once loaded, give up after --player-timeout
+async fn wait_jwplayer(driver: &WebDriver, player_id: &str, timeout: u64) -> Result<(), ResolveFailure> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(timeout) {
+        if let Ok(e) = driver.handle.find(By::Id(player_id)).await {
+            if let Ok(Some(a)) = e.attr("class").await {
+                if a.contains("jwplayer") {
+                    return Ok(());
                 }
             }
         }
-        Err(e) => {
-            error!("Can't get .m3u8 {name} (probably 404)\n{:?}", e);
-            nb_error += 1;
-        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
     }
+    Err(ResolveFailure::PlayerNotLoaded(timeout))
+}
 
-    Ok((nb_found, nb_error))
+async fn find_and_get_m3u8(driver: &WebDriver, path: &AllPath, client: &Client, args: &Args, provider: &dyn Provider, headers: &[(String, String)]) -> Result<PathBuf, ResolveFailure> {
+    let name = utils_data::edit_for_windows_compatibility(
+        &provider.clean_title(&driver.title().await?),
+    );
+    let script = driver
+        .handle
+        .execute(r#"return jwplayer().getPlaylistItem();"#, vec![])
+        .await
+        .map_err(|e| ResolveFailure::ScriptError(e.to_string()))?;
+
+    info!("Get m3u8 for: {}", name);
+    let Some(url) = script.json()["file"].as_str() else {
+        return Err(ResolveFailure::ScriptError(format!("no file in {}", script.json())));
+    };
+    download_and_save_m3u8(
+        url,
+        &name.trim().replace(":", "").replace(" ", "_"),
+        &path.tmp_dl,
+        &client,
+        args,
+        headers,
+    )
+        .await
+        .map_err(|e| ResolveFailure::PlaylistNotFound(e.to_string()))
 }
 
-async fn download_and_save_m3u8(url: &str, file_name: &str, tmp_dl: &PathBuf, client: &Client, args: &Args) -> Result<(), Box<dyn Error>> {
-    match web::web_request(&client, &url).await {
-        Ok(body) => match body.status() {
-            StatusCode::OK => {
-                let await_response = body.text().await?;
-                let split = await_response.as_bytes();
-                let parsed = m3u8_rs::parse_playlist_res(split);
+pub async fn download_and_save_m3u8(url: &str, file_name: &str, tmp_dl: &PathBuf, client: &Client, args: &Args, headers: &[(String, String)]) -> Result<PathBuf, Box<dyn Error>> {
+    let playlist = get_playlist(url, client, headers).await?;
+    let parsed = m3u8_rs::parse_playlist_res(playlist.as_bytes());
 
-                let good_url = test_resolution(parsed, &args, &client).await;
+    let good_url = test_resolution(parsed, url, args, client, headers).await;
+    if good_url.is_empty() {
+        return Err(format!("{url}: no playlist found").into());
+    }
+    if args.debug {
+        debug!("create .m3u8 for {}", file_name);
+    }
 
-                let mut out =
-                    File::create(format!("{}/{file_name}.m3u8", tmp_dl.to_str().unwrap()))
-                        .expect("failed to create file");
+    // already a media playlist, no need to ask it again
+    let media_playlist = if good_url == url {
+        playlist
+    } else {
+        get_playlist(&good_url, client, headers).await?
+    };
 
-                if args.debug {
-                    debug!("create .m3u8 for {}", file_name);
-                }
+    // the file is only created once the playlist is there, a failed episode leave nothing to download
+    let file = tmp_dl.join(format!("{file_name}.m3u8"));
+    fs::write(
+        &file,
+        hls_downloader::with_headers(
+            &hls_downloader::resolve_playlist_uris(&media_playlist, &good_url),
+            headers,
+        ),
+    )?;
 
-                io::copy(
-                    &mut web::web_request(&client, &good_url)
-                        .await?
-                        .text()
-                        .await?
-                        .as_bytes(),
-                    &mut out,
-                )
-                    .expect("Error copy");
+    if args.debug {
+        debug!("write .m3u8 for {}", file_name);
+    }
+    Ok(file)
+}
 
-                if args.debug {
-                    debug!("write .m3u8 for {}", file_name);
-                }
-            }
-            _ => error!("Error base url check: {:?}", body.status()),
-        },
-        Err(e) => {
-            error!("fetch_url: {:?}", e)
-        }
+async fn get_playlist(url: &str, client: &Client, headers: &[(String, String)]) -> Result<String, Box<dyn Error>> {
+    let resp = web::web_request_with_headers(client, url, headers)
+        .await
+        .map_err(|e| format!("{url}: {e}"))?;
+    if resp.status() != StatusCode::OK {
+        return Err(format!("{url}: {}", resp.status()).into());
     }
-    Ok(())
+    Ok(web::read_text(resp).await?)
 }
 
-async fn test_resolution(parsed: Result<Playlist, nom::Err<nom::error::Error<&[u8]>>>, args: &Args, client: &Client) -> String {
+async fn test_resolution(parsed: Result<Playlist, nom::Err<nom::error::Error<&[u8]>>>, url: &str, args: &Args, client: &Client, headers: &[(String, String)]) -> String {
     let mut _good_url = String::new();
     match parsed {
         Ok(Playlist::MasterPlaylist(pl)) => {
             if args.debug {
                 debug!("MasterPlaylist {:#?}", pl);
             }
-            for ele in pl.variants {
-                let resolution = ele.resolution.expect("No resolution found").height;
-                let test = web::web_request(&client, &ele.uri).await;
+            let base = Url::parse(url).ok();
+            let ranked = quality::rank_variants(&pl.variants, &args.quality);
+            info!(
+                "Quality {}: {}",
+                args.quality,
+                ranked.iter().map(|v| quality::describe(v)).collect::<Vec<_>>().join(" > ")
+            );
+            for ele in ranked {
+                // variant uri can be relative to the master playlist
+                let uri = base
+                    .as_ref()
+                    .and_then(|b| b.join(&ele.uri).ok())
+                    .map(|u| u.to_string())
+                    .unwrap_or(ele.uri.clone());
+                let test = web::web_request_with_headers(&client, &uri, headers).await;
                 match test {
                     Ok(code) => match code.status() {
                         StatusCode::OK => {
-                            info!("Download as {}p", resolution);
-                            _good_url = ele.uri;
+                            info!("Download as {}", quality::describe(ele));
+                            _good_url = uri;
                             if args.debug {
                                 debug!("url .m3u8 {}", _good_url);
                             }
                             break;
                         }
                         _ => {
-                            warn!("{}p not found, try next", resolution);
+                            warn!("{} not found, try next", quality::describe(ele));
                         }
                     },
                     Err(e) => error!("m3u8 check resolution error {}", e),
                 }
             }
         }
-        Ok(Playlist::MediaPlaylist(_)) => {}
+        // no variant to choose, the url is already the media playlist
+        Ok(Playlist::MediaPlaylist(_)) => _good_url = url.to_string(),
         Err(e) => println!("Error parse m3u8 : {:?}", e),
     }
     _good_url
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::mod_file::test_server::{temp_dir, Reply, TestServer};
+
+    const MEDIA: &str = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\nseg0.ts\n#EXT-X-ENDLIST\n";
+
+    fn master() -> String {
+        "#EXTM3U\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080\n1080/index.m3u8\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=1500000,RESOLUTION=854x480\n480/index.m3u8\n"
+            .to_string()
+    }
+
+    async fn save(server: &TestServer, path: &str, dir: &PathBuf) -> Result<PathBuf, Box<dyn Error>> {
+        let args = Args::parse_from(["neko_dl"]);
+        download_and_save_m3u8(&server.url(path), "ep1", dir, &Client::new(), &args, &[]).await
+    }
+
+    #[tokio::test]
+    async fn a_missing_variant_fall_back_to_the_next() {
+        let dir = temp_dir("m3u8-fallback");
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/master.m3u8" => Reply::ok(master()),
+            "/480/index.m3u8" => Reply::ok(MEDIA),
+            _ => Reply::status(404),
+        });
+
+        save(&server, "/master.m3u8", &dir).await.unwrap();
+        let saved = fs::read_to_string(dir.join("ep1.m3u8")).unwrap();
+        assert!(saved.contains(&server.url("/480/seg0.ts")), "{saved}");
+        assert_eq!(server.requests("/1080/index.m3u8").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_media_playlist_is_saved_without_asking_it_twice() {
+        let dir = temp_dir("m3u8-media");
+        let server = TestServer::start(|_| Reply::ok(MEDIA));
+
+        save(&server, "/hls/index.m3u8", &dir).await.unwrap();
+        let saved = fs::read_to_string(dir.join("ep1.m3u8")).unwrap();
+        assert!(saved.contains(&server.url("/hls/seg0.ts")), "{saved}");
+        assert_eq!(server.requests("/hls/index.m3u8").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn no_file_when_the_playlist_is_not_found() {
+        let dir = temp_dir("m3u8-not-found");
+        let server = TestServer::start(|_| Reply::status(404));
+
+        let e = save(&server, "/master.m3u8", &dir).await.unwrap_err();
+        assert!(e.to_string().contains("404"), "{e}");
+        assert!(!dir.join("ep1.m3u8").exists());
+    }
+
+    #[tokio::test]
+    async fn no_file_when_every_variant_is_missing() {
+        let dir = temp_dir("m3u8-no-variant");
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/master.m3u8" => Reply::ok(master()),
+            _ => Reply::status(404),
+        });
+
+        let e = save(&server, "/master.m3u8", &dir).await.unwrap_err();
+        assert!(e.to_string().contains("no playlist found"), "{e}");
+        assert!(!dir.join("ep1.m3u8").exists());
+    }
+
+    #[tokio::test]
+    async fn no_file_when_the_chosen_variant_then_fail() {
+        let dir = temp_dir("m3u8-variant-gone");
+        // the variant answer the check, then is gone when it's downloaded
+        let server = TestServer::start(|req| match (req.path.as_str(), req.nth) {
+            ("/master.m3u8", _) => Reply::ok(master()),
+            ("/1080/index.m3u8", 0) => Reply::ok(MEDIA),
+            _ => Reply::status(403),
+        });
+
+        let e = save(&server, "/master.m3u8", &dir).await.unwrap_err();
+        assert!(e.to_string().contains("403"), "{e}");
+        assert!(!dir.join("ep1.m3u8").exists());
+    }
+}
@@ -0,0 +1,334 @@
+use std::{error::Error, path::PathBuf, sync::LazyLock};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+use reqwest::{Client, StatusCode, Url};
+
+use crate::{debug, info, warn};
+use crate::mod_file::{
+    cmd_line_parser::Args,
+    embed_extractor::{self, EmbedExtractor},
+    html_parser,
+    network_capture::SeenStream,
+    process_part1::{Found, Resolved},
+    provider::{Provider, Stream},
+    utils_check::AllPath,
+    utils_data,
+    web,
+};
+
+static TITLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+static SCRIPT_SRC: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)<script[^>]+src=["']([^"']+)["']"#).unwrap());
+// escaped slashes are allowed, the url is often inside a json string
+static M3U8_URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https?:(?:\\?/){2}[^"'\s<>]+?\.m3u8[^"'\s<>]*"#).unwrap());
+static ATOB: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"atob\(\s*["']([A-Za-z0-9+/=]+)["']\s*\)"#).unwrap());
+
+// what the http backend found, `failed` links are left to the browser when it's allowed
+pub struct HttpScan {
+    pub good: u16,
+    pub failed: Vec<String>,
+}
+
+pub async fn get_page(client: &Client, url: &str, referer: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let headers = match referer {
+        Some(referer) => vec![("Referer".to_string(), referer.to_string())],
+        None => vec![],
+    };
+    let resp = web::web_request_with_headers(client, url, &headers).await?;
+    if resp.status() != StatusCode::OK {
+        return Err(format!("{url}: {}", resp.status()).into());
+    }
+    Ok(web::read_text(resp).await?)
+}
+
+pub fn page_title(html: &str) -> Option<String> {
+    TITLE.captures(html).map(|c| html_unescape(c[1].trim()))
+}
+
+// give the player page then its scripts to every extractor made for this host
+pub async fn find_stream_in_player(client: &Client, player_url: &str, referer: &str) -> Result<Option<Stream>, Box<dyn Error>> {
+    let extractors = embed_extractor::for_url(player_url);
+    let html = get_page(client, player_url, Some(referer)).await?;
+    if let Some(stream) = extract(&extractors, &html, player_url) {
+        return Ok(Some(stream));
+    }
+
+    let base = Url::parse(player_url)?;
+    for src in SCRIPT_SRC.captures_iter(&html) {
+        let Ok(src) = base.join(&src[1]) else {
+            continue;
+        };
+        // only scripts of the player host, cdn libraries never contain the stream
+        if src.host_str() != base.host_str() {
+            continue;
+        }
+        if let Ok(js) = get_page(client, src.as_str(), Some(player_url)).await {
+            if let Some(stream) = extract(&extractors, &js, player_url) {
+                return Ok(Some(stream));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn extract(extractors: &[&dyn EmbedExtractor], text: &str, player_url: &str) -> Option<Stream> {
+    extractors.iter().find_map(|e| {
+        e.extract(text, player_url).map(|url| Stream {
+            name: String::new(),
+            url,
+            headers: e.headers(player_url),
+        })
+    })
+}
+
+// a playlist url in the text or in base64 blobs given to atob()
+pub fn find_m3u8_in_text(text: &str) -> Option<String> {
+    if let Some(found) = M3U8_URL.find(text) {
+        return Some(found.as_str().replace("\\/", "/"));
+    }
+
+    atob_strings(text)
+        .iter()
+        .find_map(|decoded| M3U8_URL.find(decoded).map(|f| f.as_str().replace("\\/", "/")))
+}
+
+// every base64 string given to atob() that decode to text
+pub fn atob_strings(text: &str) -> Vec<String> {
+    ATOB
+        .captures_iter(text)
+        .filter_map(|c| STANDARD.decode(&c[1]).ok())
+        .filter_map(|decoded| String::from_utf8(decoded).ok())
+        .collect()
+}
+
+pub fn html_unescape(s: &str) -> String {
+    s.replace("&amp;", "&")
+        .replace("&#039;", "'")
+        .replace("&#39;", "'")
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+}
+
+// same folder name as process_part1::get_name_based_on_url without opening chrome
+pub async fn season_save_path(client: &Client, url: &str, args: &Args, provider: &dyn Provider) -> Result<Option<String>, Box<dyn Error>> {
+    let name = provider.series_name(client, url).await?;
+    Ok(name.map(|name| {
+        format!(
+            "Anime_Download/{}/{}",
+            args.language.to_uppercase(),
+            utils_data::edit_for_windows_compatibility(&name.replace(" ", "_"))
+        )
+    }))
+}
+
+// None mean the page can't be read without a browser
+pub async fn episode_links(url: &str, client: &Client, args: &Args, provider: &dyn Provider) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let links = provider.list_episodes(client, url).await?;
+
+    if links.is_empty() {
+        if args.debug {
+            debug!("http backend: no episode list in {url}");
+        }
+        return Ok(None);
+    }
+    Ok(Some(links))
+}
+
+// each playlist is handed to the downloads as soon as it's saved
+pub async fn scan(links: Vec<String>, path: &AllPath, client: &Client, args: &Args, provider: &dyn Provider, found: &Found) -> HttpScan {
+    let mut scan = HttpScan {
+        good: 0,
+        failed: vec![],
+    };
+    for link in links {
+        match resolve_episode(&link, path, client, args, provider).await {
+            Ok(Some(file)) => {
+                scan.good += 1;
+                let _ = found.send(Resolved::Playlist(file));
+            }
+            Ok(None) => scan.failed.push(link),
+            Err(e) => {
+                warn!("http backend: {link}: {e}");
+                scan.failed.push(link);
+            }
+        }
+    }
+    scan
+}
+
+async fn resolve_episode(link: &str, path: &AllPath, client: &Client, args: &Args, provider: &dyn Provider) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let Some(stream) = provider.resolve_stream(client, link, args.debug).await? else {
+        return Ok(None);
+    };
+    let name = utils_data::edit_for_windows_compatibility(&stream.name);
+
+    info!("Get stream for: {}", name);
+    let file = html_parser::save_stream_file(
+        &SeenStream::from_url(&stream.url, &stream.headers),
+        &name.trim().replace(":", "").replace(" ", "_"),
+        &path.tmp_dl,
+        client,
+        args,
+    )
+        .await?;
+    Ok(Some(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::mod_file::{
+        neko_sama::NekoSama,
+        test_server::{all_path, temp_dir, Reply, TestServer},
+    };
+
+    const SEASON: &str = include_str!("../../tests/fixtures/neko_sama/season.html");
+    const EPISODE: &str = include_str!("../../tests/fixtures/neko_sama/episode.html");
+    const PLAYER: &str = include_str!("../../tests/fixtures/neko_sama/player.html");
+    const MASTER: &str = include_str!("../../tests/fixtures/quality/master.m3u8");
+    const MEDIA: &str = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\nseg0.ts\n#EXT-X-ENDLIST\n";
+
+    // the saved pages with every host pointed to the test server, `base` is its url
+    fn neko_sama_site(base: &str, path: &str) -> Reply {
+        let episode = |number: &str, players: &[&str]| {
+            let mut html = EPISODE.replace("02 VOSTFR", &format!("{number} VOSTFR"));
+            html = html.replace("https://fusevideo.io", &format!("{base}/{}", players[0]));
+            html = html.replace("//fusevideo.io", &format!("{base}/{}", players[0]));
+            html.replace("https://pstream.net", &format!("{base}/{}", players[1]))
+        };
+        match path {
+            "/anime/info/9521-kimetsu-no-yaiba_vostfr" => {
+                Reply::ok(SEASON.replace(r#""url":"\/anime"#, &format!(r#""url":"{base}\/anime"#)))
+            }
+            "/anime/episode/9521-kimetsu-no-yaiba-01-vostfr" => Reply::ok(episode("01", &["fusevideo.io", "pstream.net"])),
+            "/anime/episode/9521-kimetsu-no-yaiba-02-vostfr" => Reply::ok(episode("02", &["fusevideo.io", "pstream.net"])),
+            // every player of the third episode is down
+            "/anime/episode/9521-kimetsu-no-yaiba-03-vostfr" => Reply::ok(episode("03", &["down", "pstream.net"])),
+            "/fusevideo.io/e/3b9Xk2" => {
+                let config = atob_strings(PLAYER)[0].replace("https://cdn.fusevideo.io", base);
+                Reply::ok(PLAYER.replace(&ATOB.captures(PLAYER).unwrap()[1], &STANDARD.encode(config)))
+            }
+            "/hls/3b9Xk2/master.m3u8" => Reply::ok(MASTER),
+            p if p.starts_with("/hls/3b9Xk2/") && p.ends_with("/index.m3u8") => Reply::ok(MEDIA),
+            _ => Reply::status(404),
+        }
+    }
+
+    #[test]
+    fn plain_playlist_url_is_found() {
+        let js = r#"player.load({ src: "https://cdn.example.com/hls/ep1/index.m3u8?token=a1" });"#;
+        assert_eq!(
+            find_m3u8_in_text(js).as_deref(),
+            Some("https://cdn.example.com/hls/ep1/index.m3u8?token=a1")
+        );
+    }
+
+    #[test]
+    fn escaped_slashes_are_unescaped() {
+        let json = r#"{"file":"https:\/\/cdn.example.com\/hls\/ep1\/index.m3u8"}"#;
+        assert_eq!(
+            find_m3u8_in_text(json).as_deref(),
+            Some("https://cdn.example.com/hls/ep1/index.m3u8")
+        );
+    }
+
+    #[test]
+    fn playlist_hidden_in_atob_is_decoded() {
+        assert_eq!(
+            find_m3u8_in_text(PLAYER).as_deref(),
+            Some("https://cdn.fusevideo.io/hls/3b9Xk2/master.m3u8?t=Zx81")
+        );
+    }
+
+    #[test]
+    fn no_playlist_no_url() {
+        assert_eq!(find_m3u8_in_text("<video src=\"/ep1.mp4\"></video> atob(\"bm9wZQ==\")"), None);
+    }
+
+    #[test]
+    fn title_is_trimmed_and_unescaped() {
+        assert_eq!(
+            page_title("<head><title>\n  Tom &amp; Jerry&#039;s 01 - Neko Sama\n</title></head>").as_deref(),
+            Some("Tom & Jerry's 01 - Neko Sama")
+        );
+        assert_eq!(page_title("<head></head>"), None);
+    }
+
+    #[tokio::test]
+    async fn scripts_of_the_player_host_are_read() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/e/ep1" => Reply::ok(
+                r#"<script src="https://cdn.example.invalid/lib.js"></script><script src="/js/player.js"></script>"#,
+            ),
+            "/js/player.js" => Reply::ok(r#"setup({ file: "https:\/\/cdn.example.com\/ep1.m3u8" })"#),
+            _ => Reply::status(404),
+        });
+
+        let stream = find_stream_in_player(&Client::new(), &server.url("/e/ep1"), "https://neko-sama.fr/anime/episode/1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stream.url, "https://cdn.example.com/ep1.m3u8");
+        assert_eq!(
+            server.requests("/e/ep1")[0].headers.get("referer").map(|r| r.as_str()),
+            Some("https://neko-sama.fr/anime/episode/1")
+        );
+        assert_eq!(server.requests("/js/player.js").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_season_is_scanned_without_browser() {
+        let server = TestServer::start(|req| {
+            let path = req.path.split('?').next().unwrap_or_default();
+            neko_sama_site(&format!("http://{}", req.headers["host"]), path)
+        });
+        let dir = temp_dir("http-scan");
+        let path = all_path(&dir);
+        fs::create_dir_all(&path.tmp_dl).unwrap();
+        let args = Args::parse_from(["neko_dl", "-i", "-l", "vostfr"]);
+        let client = Client::new();
+        let season = server.url("/anime/info/9521-kimetsu-no-yaiba_vostfr");
+
+        assert_eq!(
+            season_save_path(&client, &season, &args, &NekoSama).await.unwrap().as_deref(),
+            Some("Anime_Download/VOSTFR/Kimetsu_no_Yaiba_VOSTFR")
+        );
+        let links = episode_links(&season, &client, &args, &NekoSama).await.unwrap().unwrap();
+        assert_eq!(links.len(), 3);
+
+        let (found, mut resolved) = unbounded_channel();
+        let scan = scan(links.clone(), &path, &client, &args, &NekoSama, &found).await;
+        drop(found);
+
+        assert_eq!(scan.good, 2);
+        // left to chrome, unless --backend http
+        assert_eq!(scan.failed, vec![links[2].clone()]);
+
+        let mut files = vec![];
+        while let Some(Resolved::Playlist(file)) = resolved.recv().await {
+            files.push(file);
+        }
+        assert_eq!(
+            files,
+            vec![
+                path.tmp_dl.join("Kimetsu_no_Yaiba_01_VOSTFR.m3u8"),
+                path.tmp_dl.join("Kimetsu_no_Yaiba_02_VOSTFR.m3u8"),
+            ]
+        );
+        let playlist = fs::read_to_string(&files[0]).unwrap();
+        assert!(playlist.contains(&format!("#NEKO-DL-HEADER:Referer: {}/", server.url)), "{playlist}");
+        assert!(playlist.contains(&server.url("/hls/3b9Xk2/")), "{playlist}");
+
+        // the player is asked with the episode as referer
+        assert_eq!(
+            server.requests("/fusevideo.io/e/3b9Xk2")[0].headers.get("referer").map(|r| r.as_str()),
+            Some(links[0].as_str())
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+// keep track of finished segments of an episode in tmp/resume/<episode>/ so a rerun can continue
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Journal {
+    pub output: String,
+    pub segments: usize,
+    pub done: BTreeSet<usize>,
+}
+
+impl Journal {
+    pub fn dir(resume_path: &Path, output: &str) -> PathBuf {
+        let stem = Path::new(output)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(output);
+        resume_path.join(stem)
+    }
+
+    pub fn load_or_new(dir: &Path, output: &str, segments: usize) -> Result<Journal, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        if let Ok(content) = fs::read_to_string(dir.join("journal.json")) {
+            if let Ok(journal) = serde_json::from_str::<Journal>(&content) {
+                // same episode and same playlist, keep finished segments
+                if journal.output == output && journal.segments == segments {
+                    return Ok(journal);
+                }
+            }
+        }
+        let journal = Journal {
+            output: output.to_string(),
+            segments,
+            done: BTreeSet::new(),
+        };
+        journal.save(dir)?;
+        Ok(journal)
+    }
+
+    pub fn segment_path(dir: &Path, index: usize) -> PathBuf {
+        dir.join(format!("{index}.ts"))
+    }
+
+    pub fn is_done(&self, dir: &Path, index: usize) -> bool {
+        self.done.contains(&index) && Journal::segment_path(dir, index).is_file()
+    }
+
+    pub fn mark_done(&mut self, dir: &Path, index: usize) -> Result<(), Box<dyn Error>> {
+        self.done.insert(index);
+        self.save(dir)
+    }
+
+    // write then rename, a crash never leave a half written journal
+    fn save(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = dir.join("journal.json.tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(tmp, dir.join("journal.json"))?;
+        Ok(())
+    }
+
+    pub fn discard(dir: &Path) {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+// final mp4 present without journal mean the episode was verified in a previous run
+pub fn is_finished(resume_path: &Path, output: &Path) -> bool {
+    let Some(output) = output.to_str() else {
+        return false;
+    };
+    let has_video = fs::metadata(output).map(|m| m.len() > 0).unwrap_or(false);
+    has_video && !Journal::dir(resume_path, output).exists()
+}
@@ -0,0 +1,181 @@
+use std::{collections::HashSet, error::Error, sync::LazyLock};
+
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::debug;
+use crate::mod_file::{
+    cmd_line_parser::Args,
+    http_scraper::{self, get_page, html_unescape, page_title},
+    provider::{BrowserPage, Provider, Stream},
+    search::{self, ProcessingUrl},
+    static_data::BASE_URL,
+};
+
+static SERIES_LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)<h2[^>]*>\s*<a[^>]*href="[^"]*/anime/info/[^"]*"[^>]*>(.*?)</a>"#).unwrap());
+static EPISODES_VAR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)var\s+episodes\s*=\s*(\[.*?\]);").unwrap());
+static VIDEO_VAR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"video\[\d+\]\s*=\s*['"]([^'"]+)['"]"#).unwrap());
+static IFRAME_SRC: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)<iframe[^>]+src=["']([^"']+)["']"#).unwrap());
+
+pub struct NekoSama;
+
+#[async_trait(?Send)]
+impl Provider for NekoSama {
+    fn name(&self) -> &'static str {
+        "neko-sama"
+    }
+
+    fn base_url(&self) -> &'static str {
+        BASE_URL
+    }
+
+    fn handles(&self, host: &str) -> bool {
+        host == "neko-sama.fr" || host.ends_with(".neko-sama.fr")
+    }
+
+    fn is_episode_url(&self, url: &str) -> bool {
+        url.contains("/episode/")
+    }
+
+    fn clean_title(&self, title: &str) -> String {
+        title.replace(" - Neko Sama", "")
+    }
+
+    fn browser(&self) -> BrowserPage {
+        BrowserPage {
+            next_page_class: "animeps-next-page",
+            episode_link_classes: &["play", "text-left"],
+            player_frame_id: "un_episode",
+            player_id: "main-player",
+            series_xpath: r#"//*[@id="watch"]/div/div[4]/div[1]/div/div/h2/a"#,
+        }
+    }
+
+    async fn search(&self, keyword: &str, args: &Args) -> Result<Vec<ProcessingUrl>, Box<dyn Error>> {
+        search::search_over_json(BASE_URL, keyword, args).await
+    }
+
+    async fn series_name(&self, client: &Client, url: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let html = get_page(client, url, None).await?;
+        let title = page_title(&html).map(|t| self.clean_title(&t));
+        if self.is_episode_url(url) {
+            Ok(series_name_from_episode(&html).or(title))
+        } else {
+            Ok(title)
+        }
+    }
+
+    async fn list_episodes(&self, client: &Client, url: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.is_episode_url(url) {
+            return Ok(vec![url.to_string()]);
+        }
+        let html = get_page(client, url, None).await?;
+        Ok(episode_links(&html)
+            .iter()
+            .map(|link| self.absolute_url(link))
+            .collect())
+    }
+
+    async fn resolve_stream(&self, client: &Client, episode_url: &str, debug: bool) -> Result<Option<Stream>, Box<dyn Error>> {
+        let html = get_page(client, episode_url, None).await?;
+        let name = page_title(&html)
+            .map(|t| self.clean_title(&t))
+            .unwrap_or(episode_url.to_string());
+
+        for player in player_urls(&html) {
+            if debug {
+                debug!("http backend: player {player} for {name}");
+            }
+            if let Ok(Some(stream)) = http_scraper::find_stream_in_player(client, &player, episode_url).await {
+                return Ok(Some(Stream { name, ..stream }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// the season name shown above the player of an episode page
+fn series_name_from_episode(html: &str) -> Option<String> {
+    SERIES_LINK.captures(html).map(|c| html_unescape(c[1].trim()))
+}
+
+// the season page embed every episode in `var episodes = [...]`, no pagination like the browser
+fn episode_links(html: &str) -> Vec<String> {
+    let Some(json) = EPISODES_VAR.captures(html) else {
+        return vec![];
+    };
+    let Ok(episodes) = serde_json::from_str::<Vec<Value>>(&json[1]) else {
+        return vec![];
+    };
+    episodes
+        .iter()
+        .filter_map(|e| e["url"].as_str())
+        .map(|url| url.to_string())
+        .collect()
+}
+
+// players are set from `video[n] = '...'` in a script, or directly in an iframe
+fn player_urls(html: &str) -> Vec<String> {
+    let mut players: Vec<String> = VIDEO_VAR
+        .captures_iter(html)
+        .chain(IFRAME_SRC.captures_iter(html))
+        .map(|c| c[1].to_string())
+        .filter(|url| url.starts_with("http") || url.starts_with("//"))
+        .map(|url| if url.starts_with("//") { format!("https:{url}") } else { url })
+        .collect();
+    // the iframe often repeat video[0], the first one keep its place
+    let mut seen = HashSet::new();
+    players.retain(|url| seen.insert(url.clone()));
+    players
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEASON: &str = include_str!("../../tests/fixtures/neko_sama/season.html");
+    const EPISODE: &str = include_str!("../../tests/fixtures/neko_sama/episode.html");
+
+    #[test]
+    fn episodes_are_listed_from_the_season_script() {
+        assert_eq!(
+            episode_links(SEASON),
+            vec![
+                "/anime/episode/9521-kimetsu-no-yaiba-01-vostfr",
+                "/anime/episode/9521-kimetsu-no-yaiba-02-vostfr",
+                "/anime/episode/9521-kimetsu-no-yaiba-03-vostfr",
+            ]
+        );
+        assert_eq!(NekoSama.absolute_url(&episode_links(SEASON)[0]), format!("{BASE_URL}/anime/episode/9521-kimetsu-no-yaiba-01-vostfr"));
+    }
+
+    #[test]
+    fn a_page_without_episode_list_give_nothing() {
+        assert!(episode_links(EPISODE).is_empty());
+        assert!(episode_links("<script>var episodes = [{broken</script>").is_empty());
+    }
+
+    #[test]
+    fn players_are_listed_once_in_page_order() {
+        assert_eq!(
+            player_urls(EPISODE),
+            vec!["https://fusevideo.io/e/3b9Xk2", "https://pstream.net/e/Qm2vT8"]
+        );
+    }
+
+    #[test]
+    fn series_name_is_read_above_the_player() {
+        assert_eq!(
+            series_name_from_episode(EPISODE).as_deref(),
+            Some("Kimetsu no Yaiba & Tanjiro's journey")
+        );
+        assert_eq!(series_name_from_episode(SEASON), None);
+        assert_eq!(
+            page_title(EPISODE).map(|t| NekoSama.clean_title(&t)).as_deref(),
+            Some("Kimetsu no Yaiba 02 VOSTFR")
+        );
+    }
+}
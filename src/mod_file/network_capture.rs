@@ -0,0 +1,251 @@
+use std::error::Error;
+
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use thirtyfour::{
+    common::command::FormatRequestData, error::WebDriverResult, CapabilitiesHelper, ChromeCapabilities,
+    RequestData, RequestMethod, SessionId, WebDriver,
+};
+
+use crate::mod_file::web;
+
+// chromedriver keep every devtools event of the page in the "performance" log
+pub fn enable(prefs: &mut ChromeCapabilities) -> Result<(), Box<dyn Error>> {
+    prefs.set_base_capability("goog:loggingPrefs", json!({ "performance": "ALL" }))?;
+    Ok(())
+}
+
+// not in the w3c protocol, chromedriver still answer the legacy endpoint
+#[derive(Debug)]
+struct GetLog;
+
+impl FormatRequestData for GetLog {
+    fn format_request(&self, session_id: &SessionId) -> RequestData {
+        RequestData::new(RequestMethod::Post, format!("/session/{session_id}/se/log"))
+            .add_body(json!({ "type": "performance" }))
+    }
+}
+
+// a playlist or a video the page asked for, with the headers chrome sent so the host accept us too
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeenStream {
+    pub url: String,
+    pub kind: StreamKind,
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamKind {
+    Playlist,
+    Video,
+}
+
+impl SeenStream {
+    // a url read in a page, nothing but its extension tell what it is
+    pub fn from_url(url: &str, headers: &[(String, String)]) -> SeenStream {
+        SeenStream {
+            url: url.to_string(),
+            kind: match stream_kind(url, None) {
+                Some(StreamKind::Video) => StreamKind::Video,
+                _ => StreamKind::Playlist,
+            },
+            headers: headers.to_vec(),
+        }
+    }
+}
+
+// set by our own client, or by the connection itself
+const SKIPPED_HEADERS: &[&str] = &[
+    "user-agent",
+    "accept",
+    "accept-language",
+    "accept-encoding",
+    "upgrade-insecure-requests",
+    "range",
+    "host",
+    "connection",
+    "content-length",
+];
+
+// playlists and videos requested since the last call, the log is emptied by reading it
+pub async fn stream_urls(driver: &WebDriver) -> WebDriverResult<Vec<SeenStream>> {
+    let entries: Vec<Value> = driver.handle.cmd(GetLog).await?.value()?;
+    Ok(streams_in_log(&entries))
+}
+
+fn streams_in_log(entries: &[Value]) -> Vec<SeenStream> {
+    let mut streams: Vec<SeenStream> = vec![];
+    for entry in entries {
+        let Some(message) = entry["message"].as_str() else {
+            continue;
+        };
+        let Ok(message) = serde_json::from_str::<Value>(message) else {
+            continue;
+        };
+        let params = &message["message"]["params"];
+        let (url, mime, headers) = match message["message"]["method"].as_str() {
+            Some("Network.requestWillBeSent") => (
+                params["request"]["url"].as_str(),
+                None,
+                request_headers(&params["request"]["headers"]),
+            ),
+            Some("Network.responseReceived") => (
+                params["response"]["url"].as_str(),
+                params["response"]["mimeType"].as_str(),
+                vec![],
+            ),
+            _ => continue,
+        };
+        let Some(url) = url else {
+            continue;
+        };
+        // only the request know the headers, a stream known by its mime type is found on the response
+        let kind = stream_kind(url, mime);
+        match streams.iter_mut().find(|s| s.url == url) {
+            Some(seen) => {
+                if seen.headers.is_empty() {
+                    seen.headers = headers;
+                }
+                // the mime type is more reliable than the extension
+                if let (Some(kind), Some(_)) = (kind, mime) {
+                    seen.kind = kind;
+                }
+            }
+            None => {
+                if let Some(kind) = kind {
+                    streams.push(SeenStream {
+                        url: url.to_string(),
+                        kind,
+                        headers,
+                    });
+                }
+            }
+        }
+    }
+    streams
+}
+
+fn request_headers(headers: &Value) -> Vec<(String, String)> {
+    let Some(headers) = headers.as_object() else {
+        return vec![];
+    };
+    headers
+        .iter()
+        .filter(|(key, _)| !key.starts_with(':') && !SKIPPED_HEADERS.contains(&key.to_lowercase().as_str()))
+        .filter_map(|(key, value)| value.as_str().map(|v| (key.to_string(), v.to_string())))
+        .collect()
+}
+
+fn stream_kind(url: &str, mime: Option<&str>) -> Option<StreamKind> {
+    match mime.map(|m| m.to_lowercase()) {
+        Some(m) if m.contains("mpegurl") => return Some(StreamKind::Playlist),
+        Some(m) if m == "video/mp4" => return Some(StreamKind::Video),
+        _ => {}
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+    if path.ends_with(".m3u8") {
+        Some(StreamKind::Playlist)
+    } else if path.ends_with(".mp4") {
+        Some(StreamKind::Video)
+    } else {
+        None
+    }
+}
+
+// a master playlist first so --quality can choose, then any playlist, then a plain video
+pub async fn pick_stream(client: &Client, streams: &[SeenStream]) -> Option<SeenStream> {
+    let playlists: Vec<&SeenStream> = streams.iter().filter(|s| s.kind == StreamKind::Playlist).collect();
+    for stream in &playlists {
+        if let Ok(resp) = web::web_request_with_headers(client, &stream.url, &stream.headers).await {
+            if resp.status() != StatusCode::OK {
+                continue;
+            }
+            if let Ok(text) = web::read_text(resp).await {
+                if text.contains("#EXT-X-STREAM-INF") {
+                    return Some((*stream).clone());
+                }
+            }
+        }
+    }
+    playlists
+        .first()
+        .map(|s| (*s).clone())
+        .or(streams.first().cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mod_file::test_server::{Reply, TestServer};
+
+    fn log() -> Vec<Value> {
+        serde_json::from_str(include_str!("../../tests/fixtures/network/performance_log.json")).unwrap()
+    }
+
+    #[test]
+    fn streams_keep_the_headers_of_their_request() {
+        let streams = streams_in_log(&log());
+        assert_eq!(
+            streams.iter().map(|s| s.url.as_str()).collect::<Vec<_>>(),
+            vec![
+                "https://cdn.fusevideo.io/hls/3b9Xk2/master.m3u8?t=Zx81",
+                "https://cdn.fusevideo.io/hls/3b9Xk2/720p?t=Zx81",
+                "https://cdn.fusevideo.io/v/3b9Xk2?t=Zx81",
+            ]
+        );
+        assert_eq!(
+            streams[0].headers,
+            vec![
+                ("Origin".to_string(), "https://fusevideo.io".to_string()),
+                ("Referer".to_string(), "https://fusevideo.io/".to_string()),
+                ("sec-ch-ua-platform".to_string(), "\"Linux\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn headers_seen_after_the_response_are_kept() {
+        let streams = streams_in_log(&log());
+        assert_eq!(
+            streams[1].headers,
+            vec![("Referer".to_string(), "https://fusevideo.io/".to_string())]
+        );
+    }
+
+    #[test]
+    fn a_video_without_extension_is_known_by_its_mime_type() {
+        let streams = streams_in_log(&log());
+        assert_eq!(streams[0].kind, StreamKind::Playlist);
+        assert_eq!(streams[1].kind, StreamKind::Playlist);
+        assert_eq!(streams[2].url, "https://cdn.fusevideo.io/v/3b9Xk2?t=Zx81");
+        assert_eq!(streams[2].kind, StreamKind::Video);
+    }
+
+    #[test]
+    fn kind_come_from_the_mime_type_then_the_extension() {
+        assert_eq!(stream_kind("https://cdn.example/hls/720p?t=1", None), None);
+        assert_eq!(stream_kind("https://cdn.example/index.m3u8", None), Some(StreamKind::Playlist));
+        assert_eq!(stream_kind("https://cdn.example/ep1.MP4?t=1#start", None), Some(StreamKind::Video));
+        assert_eq!(stream_kind("https://cdn.example/v/1", Some("video/MP4")), Some(StreamKind::Video));
+        assert_eq!(stream_kind("https://cdn.example/ep1.mp4", Some("application/x-mpegURL")), Some(StreamKind::Playlist));
+    }
+
+    #[test]
+    fn a_scraped_url_is_a_playlist_unless_it_end_with_mp4() {
+        assert_eq!(SeenStream::from_url("https://cdn.example/hls/720p?t=1", &[]).kind, StreamKind::Playlist);
+        assert_eq!(SeenStream::from_url("https://cdn.example/ep1.mp4", &[]).kind, StreamKind::Video);
+    }
+
+    #[tokio::test]
+    async fn a_plain_video_is_never_read_as_a_playlist() {
+        let server = TestServer::start(|_| Reply::ok("not a playlist"));
+        let video = SeenStream {
+            url: server.url("/v/3b9Xk2"),
+            kind: StreamKind::Video,
+            headers: vec![],
+        };
+
+        assert_eq!(pick_stream(&Client::new(), &[video.clone()]).await, Some(video));
+        assert!(server.requests("/v/3b9Xk2").is_empty());
+    }
+}
@@ -7,32 +7,88 @@ use std::{
 };
 
 use reqwest::Client;
+use tokio::sync::mpsc::UnboundedSender;
 use thirtyfour::{ChromeCapabilities, ChromiumLikeCapabilities, WebDriver};
 
 use crate::{debug, error, info, warn};
 use crate::mod_file::{
     {html_parser, html_parser::get_base_name_direct_url},
+    driver_pool::DriverPool,
+    http_scraper,
+    provider::Provider,
     {utils_data, utils_data::ask_something},
-    cmd_line_parser::Args,
+    cmd_line_parser::{Args, Resolver},
+    network_capture,
     utils_check::AllPath,
     vlc_playlist_builder,
 };
 
 
-pub(crate) async fn scan_main(driver: &WebDriver, url_test: &str, path: &AllPath, client: &Client, args: &Args) -> Result<(String, u16, u16), Box<dyn Error>> {
+// what the scan hand to the downloads as it goes, the season folder always come before its episodes
+pub(crate) enum Resolved {
+    SavePath(String),
+    Playlist(PathBuf),
+}
+
+pub(crate) type Found = UnboundedSender<Resolved>;
+
+// what every step of a season scan read, the episodes go to `found`
+struct SeasonScan<'a> {
+    path: &'a AllPath,
+    client: &'a Client,
+    args: &'a Args,
+    provider: &'a dyn Provider,
+    found: &'a Found,
+}
+
+pub(crate) async fn scan_main(pool: &mut DriverPool, url_test: &str, path: &AllPath, client: &Client, args: &Args, provider: &dyn Provider, found: &Found) -> Result<(String, u16, u16), Box<dyn Error>> {
     info!("Scan Main Page");
     let mut save_path = String::new();
+    let scan = SeasonScan {
+        path,
+        client,
+        args,
+        provider,
+        found,
+    };
 
-    let (good, error) =
-        build_path_to_save_final_video(&mut save_path, &driver, url_test, path, &client, &args)
-            .await?;
+    let (good, error) = build_path_to_save_final_video(&mut save_path, pool, url_test, &scan).await?;
 
     info!("total found: {}", good);
 
     Ok((save_path, good, error))
 }
 
-pub(crate) fn prevent_case_nothing_found_or_error(good: u16, error: u16, args: &Args) {
+// None mean the page need a browser, failed links are returned for the browser fallback
+pub(crate) async fn scan_main_http(url_test: &str, path: &AllPath, client: &Client, args: &Args, provider: &dyn Provider, found: &Found) -> Result<Option<(String, u16, u16, Vec<String>)>, Box<dyn Error>> {
+    info!("Scan Main Page without browser");
+    fs::create_dir_all(&path.tmp_dl)?;
+
+    let Some(save_path) = http_scraper::season_save_path(client, url_test, args, provider).await? else {
+        return Ok(None);
+    };
+    let Some(links) = http_scraper::episode_links(url_test, client, args, provider).await? else {
+        return Ok(None);
+    };
+
+    // the season folder is ready before the first download start
+    prepare_season_path(&save_path, path, args)?;
+    let _ = found.send(Resolved::SavePath(save_path.clone()));
+
+    let scan = http_scraper::scan(links, path, client, args, provider, found).await;
+
+    info!("total found: {}", scan.good);
+
+    Ok(Some((save_path, scan.good, 0, scan.failed)))
+}
+
+// false when the user cancel, the episodes already downloading are left to finish
+pub(crate) fn prevent_case_nothing_found_or_error(good: u16, error: u16, args: &Args) -> bool {
+    if good == 0 {
+        error!("Nothing found or url down");
+        exit(130);
+    }
+
     if error > 0 && args.ignore_alert_missing_episode {
         if let Ok(e) =
             ask_something("Continue with missing episode(s) ? 'Y' continue, 'n' to cancel : ")
@@ -40,15 +96,11 @@ pub(crate) fn prevent_case_nothing_found_or_error(good: u16, error: u16, args: &
             if e.as_bool().unwrap() {
                 info!("Okay continue")
             } else {
-                exit(130);
+                return false;
             }
         }
     }
-
-    if good == 0 {
-        error!("Nothing found or url down");
-        exit(130);
-    }
+    true
 }
 
 pub(crate) async fn shutdown_chrome(args: &Args, driver: &WebDriver) {
@@ -75,44 +127,34 @@ pub fn add_ublock(args: &Args, path: &AllPath) -> Result<ChromeCapabilities, Box
         .add_extension(&*path.u_block_path)
         .expect("can't install ublock origin");
     prefs.set_ignore_certificate_errors()?;
+    if args.resolver != Resolver::Jwplayer {
+        network_capture::enable(&mut prefs)?;
+    }
+    if args.headless {
+        if args.debug {
+            debug!("chrome headless");
+        }
+        // the new headless mode is a real chrome without window, the old one can't load extensions
+        prefs.add_arg("--headless=new")?;
+        prefs.add_arg("--window-size=1920,1080")?;
+        prefs.add_arg("--disable-gpu")?;
+        // containers often have a tiny /dev/shm, and run as root where chrome refuse to start with its sandbox
+        prefs.add_arg("--disable-dev-shm-usage")?;
+        prefs.add_arg("--no-sandbox")?;
+    }
     Ok(prefs)
 }
 
-pub(crate) fn build_vec_m3u8_folder_path(path: &AllPath, save_path: String) -> Result<(Vec<(PathBuf, PathBuf)>, Vec<(PathBuf, String)>), Box<dyn Error>> {
-    let mut save_path_vlc = vec![];
-
-    let m3u8_path_folder: Vec<_> = fs::read_dir(&path.tmp_dl)?
-        .filter_map(|entry| {
-            let save = &mut save_path_vlc;
-
-            let entry = entry.ok();
-            let file_path = entry?.path();
-
-            if file_path.is_file() {
-                let output_path = Path::new(&path.tmp_dl).join(file_path.file_name()?);
-                let name = path
-                    .exe_path
-                    .parent()
-                    .unwrap()
-                    .join(save_path.clone())
-                    .join(utils_data::edit_for_windows_compatibility(
-                        &file_path
-                            .file_name()
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .replace(".m3u8", ".mp4")
-                            .replace(" ", "_"),
-                    ));
-                save.push((name.clone(), save_path.clone()));
-                Some((output_path, name))
-            } else {
-                None
-            }
-        })
-        .collect();
-
-    Ok((m3u8_path_folder, save_path_vlc))
+// the video of a playlist or a direct video link saved in tmp/, named after it in the season folder
+pub(crate) fn episode_output(path: &AllPath, save_path: &str, file: &Path) -> Option<PathBuf> {
+    let name = file.with_extension("mp4").file_name()?.to_str()?.replace(" ", "_");
+    Some(
+        path.exe_path
+            .parent()
+            .unwrap()
+            .join(save_path)
+            .join(utils_data::edit_for_windows_compatibility(&name)),
+    )
 }
 
 pub(crate) fn build_vlc_playlist(good: u16, args: &Args, mut save_path_vlc: Vec<(PathBuf, String)>) -> Result<(), Box<dyn Error>> {
@@ -125,13 +167,13 @@ pub(crate) fn build_vlc_playlist(good: u16, args: &Args, mut save_path_vlc: Vec<
 }
 
 
-pub async fn connect_to_chrome_driver(args: &Args, prefs: ChromeCapabilities, url_test: &str) -> Result<WebDriver, Box<dyn Error>> {
+pub async fn connect_to_chrome_driver(args: &Args, prefs: ChromeCapabilities, driver_url: &str, url_test: &str) -> Result<WebDriver, Box<dyn Error>> {
     if args.debug {
         debug!("connect to chrome driver");
     }
 
-    let driver = WebDriver::new("http://localhost:6969", prefs).await?;
-    if args.minimized_chrome {
+    let driver = WebDriver::new(driver_url, prefs).await?;
+    if args.minimized_chrome && !args.headless {
         driver.minimize_window().await?;
     }
     driver
@@ -143,12 +185,18 @@ pub async fn connect_to_chrome_driver(args: &Args, prefs: ChromeCapabilities, ur
     Ok(driver)
 }
 
-async fn build_path_to_save_final_video(save_path: &mut String, drivers: &WebDriver, url_test: &str, path: &AllPath, client: &Client, args: &Args) -> Result<(u16, u16), Box<dyn Error>> {
-    fs::create_dir_all(&path.tmp_dl)?;
+async fn build_path_to_save_final_video(save_path: &mut String, pool: &mut DriverPool, url_test: &str, scan: &SeasonScan<'_>) -> Result<(u16, u16), Box<dyn Error>> {
+    fs::create_dir_all(&scan.path.tmp_dl)?;
 
-    let mut _name = get_name_based_on_url(url_test, args, &drivers).await?;
+    let mut _name = get_name_based_on_url(url_test, scan.args, pool.first(), scan.provider).await?;
     save_path.push_str(_name.as_str());
 
+    prepare_season_path(save_path, scan.path, scan.args)?;
+    let _ = scan.found.send(Resolved::SavePath(save_path.clone()));
+    html_parser::recursive_find_url(pool, url_test, scan.args, scan.client, scan.path, scan.provider, scan.found).await
+}
+
+fn prepare_season_path(save_path: &str, path: &AllPath, args: &Args) -> Result<(), Box<dyn Error>> {
     let season_path = path.tmp_dl.parent().unwrap().join(save_path);
     if args.ignore_alert_missing_episode {
         if fs::try_exists(season_path.clone()).unwrap() {
@@ -165,19 +213,17 @@ async fn build_path_to_save_final_video(save_path: &mut String, drivers: &WebDri
     }
 
     fs::create_dir_all(season_path)?;
-    Ok(html_parser::recursive_find_url(&drivers, url_test, args, &client, path).await?)
+    Ok(())
 }
 
-async fn get_name_based_on_url(url_test: &str, args: &Args, drivers: &WebDriver) -> Result<String, Box<dyn Error>> {
-    let _path = if !url_test.contains("/episode/") {
+async fn get_name_based_on_url(url_test: &str, args: &Args, drivers: &WebDriver, provider: &dyn Provider) -> Result<String, Box<dyn Error>> {
+    let _path = if !provider.is_episode_url(url_test) {
         format!(
             "Anime_Download/{}/{}",
             args.language.to_uppercase(),
             &utils_data::edit_for_windows_compatibility(
-                &drivers
-                    .title()
-                    .await?
-                    .replace(" - Neko Sama", "")
+                &provider
+                    .clean_title(&drivers.title().await?)
                     .replace(" ", "_")
             )
         )
@@ -186,9 +232,8 @@ async fn get_name_based_on_url(url_test: &str, args: &Args, drivers: &WebDriver)
             "Anime_Download/{}/{}",
             args.language.to_uppercase(),
             &utils_data::edit_for_windows_compatibility(
-                &get_base_name_direct_url(&drivers)
-                    .await
-                    .replace(" - Neko Sama", "")
+                &provider
+                    .clean_title(&get_base_name_direct_url(&drivers, provider).await)
                     .replace(" ", "_")
             )
         )
@@ -198,7 +243,7 @@ async fn get_name_based_on_url(url_test: &str, args: &Args, drivers: &WebDriver)
 
 pub(crate) fn end_print(before: Instant, path: &AllPath, good: u16, error: u16) {
     info!("Clean tmp dir!");
-    utils_data::remove_dir_contents(&path.tmp_dl);
+    utils_data::remove_tmp_files(&path.tmp_dl);
     info!(
         "Done in: {} for {} episodes and {} error",
         utils_data::time_to_human_time(before),
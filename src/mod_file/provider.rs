@@ -0,0 +1,102 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+
+use crate::mod_file::{
+    cmd_line_parser::{Args, Scan},
+    neko_sama::NekoSama,
+    search::ProcessingUrl,
+};
+
+// a stream found on an episode page, `name` become the file name
+pub struct Stream {
+    pub name: String,
+    pub url: String,
+    // sent with every request of the episode, the downloaders included
+    pub headers: Vec<(String, String)>,
+}
+
+// what chrome look for when the page can't be read over http
+pub struct BrowserPage {
+    pub next_page_class: &'static str,
+    // first class with at least one link win
+    pub episode_link_classes: &'static [&'static str],
+    pub player_frame_id: &'static str,
+    pub player_id: &'static str,
+    // season name shown on an episode page
+    pub series_xpath: &'static str,
+}
+
+// one site we can download from, add a new one in PROVIDERS
+#[async_trait(?Send)]
+pub trait Provider: Sync {
+    // value of --provider
+    fn name(&self) -> &'static str;
+
+    fn base_url(&self) -> &'static str;
+
+    // direct download urls are given to the provider owning the host
+    fn handles(&self, host: &str) -> bool;
+
+    fn is_episode_url(&self, url: &str) -> bool;
+
+    // page title without the site name
+    fn clean_title(&self, title: &str) -> String;
+
+    fn browser(&self) -> BrowserPage;
+
+    async fn search(&self, keyword: &str, args: &Args) -> Result<Vec<ProcessingUrl>, Box<dyn Error>>;
+
+    // None mean the name can't be read without browser
+    async fn series_name(&self, client: &Client, url: &str) -> Result<Option<String>, Box<dyn Error>>;
+
+    // absolute url of every episode page of a season, empty if a browser is needed
+    async fn list_episodes(&self, client: &Client, url: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    async fn resolve_stream(&self, client: &Client, episode_url: &str, debug: bool) -> Result<Option<Stream>, Box<dyn Error>>;
+
+    fn absolute_url(&self, link: &str) -> String {
+        Url::parse(self.base_url())
+            .and_then(|base| base.join(link))
+            .map(|url| url.to_string())
+            .unwrap_or(link.to_string())
+    }
+}
+
+static PROVIDERS: &[&dyn Provider] = &[&NekoSama];
+
+pub fn names() -> Vec<&'static str> {
+    PROVIDERS.iter().map(|p| p.name()).collect()
+}
+
+pub fn by_name(name: &str) -> Option<&'static dyn Provider> {
+    PROVIDERS
+        .iter()
+        .find(|p| p.name().eq_ignore_ascii_case(name))
+        .copied()
+}
+
+pub fn by_host(host: &str) -> Option<&'static dyn Provider> {
+    PROVIDERS.iter().find(|p| p.handles(host)).copied()
+}
+
+// --provider win, then the host of a direct url, search use the first provider
+pub fn select(args: &Args) -> Result<&'static dyn Provider, String> {
+    if let Some(name) = &args.provider {
+        return by_name(name).ok_or(format!(
+            "Unknown provider \"{name}\", available: {}",
+            names().join(", ")
+        ));
+    }
+    match &args.url_or_search_word {
+        Scan::Download(uri) => {
+            let host = uri.host().unwrap_or_default();
+            by_host(host).ok_or(format!(
+                "No provider for {host}, available: {} (force one with --provider)",
+                names().join(", ")
+            ))
+        }
+        Scan::Search(_) => Ok(PROVIDERS[0]),
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    cmp::Reverse,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use m3u8_rs::VariantStream;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quality {
+    Best,
+    Worst,
+    Height(u64),
+    MaxHeight(u64),
+    MaxBandwidth(u64),
+}
+
+impl FromStr for Quality {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Quality, String> {
+        let s = s.trim().to_lowercase();
+        let invalid = || format!("Invalid quality \"{s}\" use best, worst, 1080p, max-height=720 or max-bandwidth=3000000");
+        match s.as_str() {
+            "best" => Ok(Quality::Best),
+            "worst" => Ok(Quality::Worst),
+            _ => {
+                if let Some(height) = s.strip_prefix("max-height=") {
+                    Ok(Quality::MaxHeight(height.trim_end_matches('p').parse().map_err(|_| invalid())?))
+                } else if let Some(bandwidth) = s.strip_prefix("max-bandwidth=") {
+                    Ok(Quality::MaxBandwidth(parse_bandwidth(bandwidth).ok_or_else(invalid)?))
+                } else if let Some(height) = s.strip_suffix('p') {
+                    Ok(Quality::Height(height.parse().map_err(|_| invalid())?))
+                } else {
+                    Err(invalid())
+                }
+            }
+        }
+    }
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Best => write!(f, "best"),
+            Quality::Worst => write!(f, "worst"),
+            Quality::Height(h) => write!(f, "{h}p"),
+            Quality::MaxHeight(h) => write!(f, "max-height={h}"),
+            Quality::MaxBandwidth(b) => write!(f, "max-bandwidth={b}"),
+        }
+    }
+}
+
+// bits per second, "3000000", "3000k" or "3m"
+fn parse_bandwidth(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if let Some(k) = s.strip_suffix('k') {
+        k.parse::<u64>().ok().map(|k| k * 1000)
+    } else if let Some(m) = s.strip_suffix('m') {
+        m.parse::<f64>().ok().map(|m| (m * 1_000_000.0) as u64)
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn height(variant: &VariantStream) -> Option<u64> {
+    variant.resolution.map(|r| r.height)
+}
+
+// every variant ordered from the most to the least wanted, the caller try them in order
+// variants without RESOLUTION are kept and ranked by BANDWIDTH after the ones we can compare
+pub fn rank_variants<'a>(variants: &'a [VariantStream], quality: &Quality) -> Vec<&'a VariantStream> {
+    let mut ranked: Vec<&VariantStream> = variants.iter().filter(|v| !v.is_i_frame).collect();
+
+    // best first, the other policies start from this order
+    ranked.sort_by_key(|v| (Reverse(height(v).is_some()), Reverse(height(v)), Reverse(v.bandwidth)));
+
+    match quality {
+        Quality::Best => {}
+        Quality::Worst => {
+            ranked.sort_by_key(|v| (Reverse(height(v).is_some()), height(v), v.bandwidth));
+        }
+        Quality::Height(wanted) => {
+            // exact height, then the closest below, then the closest above
+            ranked.sort_by_key(|v| match height(v) {
+                Some(h) if h == *wanted => (0, 0, Reverse(v.bandwidth)),
+                Some(h) if h < *wanted => (1, wanted - h, Reverse(v.bandwidth)),
+                Some(h) => (2, h - wanted, Reverse(v.bandwidth)),
+                None => (3, 0, Reverse(v.bandwidth)),
+            });
+        }
+        Quality::MaxHeight(max) => {
+            ranked.sort_by_key(|v| match height(v) {
+                Some(h) if h <= *max => (0, Reverse(h), Reverse(v.bandwidth)),
+                None => (1, Reverse(0), Reverse(v.bandwidth)),
+                // nothing fit, smallest first
+                Some(h) => (2, Reverse(u64::MAX - h), Reverse(u64::MAX - v.bandwidth)),
+            });
+        }
+        Quality::MaxBandwidth(max) => {
+            ranked.sort_by_key(|v| {
+                if v.bandwidth <= *max {
+                    (0, Reverse(v.bandwidth))
+                } else {
+                    (1, Reverse(u64::MAX - v.bandwidth))
+                }
+            });
+        }
+    }
+    ranked
+}
+
+pub fn describe(variant: &VariantStream) -> String {
+    match height(variant) {
+        Some(h) => format!("{}p ({} kb/s)", h, variant.bandwidth / 1000),
+        None => format!("unknown resolution ({} kb/s)", variant.bandwidth / 1000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use m3u8_rs::Playlist;
+
+    use super::*;
+
+    // 1080p, 480p, 360p, one variant without RESOLUTION and an i-frame playlist, no 720p
+    fn variants() -> Vec<VariantStream> {
+        match m3u8_rs::parse_playlist_res(include_bytes!("../../tests/fixtures/quality/master.m3u8")) {
+            Ok(Playlist::MasterPlaylist(pl)) => pl.variants,
+            _ => panic!("fixture is not a master playlist"),
+        }
+    }
+
+    fn ranked(quality: &str) -> Vec<&'static str> {
+        let variants = variants();
+        rank_variants(&variants, &quality.parse().unwrap())
+            .iter()
+            .map(|v| match v.bandwidth {
+                5000000 => "1080p",
+                1500000 => "480p",
+                800000 => "360p",
+                2000000 => "unknown",
+                _ => "i-frame",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn best_start_with_the_highest_resolution() {
+        assert_eq!(ranked("best"), ["1080p", "480p", "360p", "unknown"]);
+    }
+
+    #[test]
+    fn worst_start_with_the_lowest_resolution() {
+        assert_eq!(ranked("worst"), ["360p", "480p", "1080p", "unknown"]);
+    }
+
+    #[test]
+    fn missing_height_fall_back_below_then_above() {
+        assert_eq!(ranked("720p"), ["480p", "360p", "1080p", "unknown"]);
+        assert_eq!(ranked("1080p"), ["1080p", "480p", "360p", "unknown"]);
+    }
+
+    #[test]
+    fn max_height_keep_the_highest_that_fit() {
+        assert_eq!(ranked("max-height=720"), ["480p", "360p", "unknown", "1080p"]);
+    }
+
+    #[test]
+    fn max_height_too_low_start_with_the_smallest() {
+        assert_eq!(ranked("max-height=240"), ["unknown", "360p", "480p", "1080p"]);
+    }
+
+    #[test]
+    fn max_bandwidth_count_variants_without_resolution() {
+        assert_eq!(ranked("max-bandwidth=2500k"), ["unknown", "480p", "360p", "1080p"]);
+    }
+
+    #[test]
+    fn max_bandwidth_too_low_start_with_the_lightest() {
+        assert_eq!(ranked("max-bandwidth=500000"), ["360p", "480p", "unknown", "1080p"]);
+    }
+
+    #[test]
+    fn quality_parse_and_display_round_trip() {
+        for quality in ["best", "worst", "720p", "max-height=720", "max-bandwidth=3000000"] {
+            assert_eq!(quality.parse::<Quality>().unwrap().to_string(), quality);
+        }
+        assert_eq!("max-bandwidth=3m".parse::<Quality>(), Ok(Quality::MaxBandwidth(3_000_000)));
+        assert!("720".parse::<Quality>().is_err());
+        assert!("max-height=big".parse::<Quality>().is_err());
+    }
+
+    #[test]
+    fn unknown_resolution_is_described() {
+        let variants = variants();
+        let described: Vec<String> = variants.iter().filter(|v| !v.is_i_frame).map(describe).collect();
+        assert_eq!(described, ["360p (800 kb/s)", "1080p (5000 kb/s)", "unknown resolution (2000 kb/s)", "480p (1500 kb/s)"]);
+    }
+}
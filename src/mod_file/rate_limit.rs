@@ -0,0 +1,283 @@
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveTime, Timelike};
+
+use crate::{info, warn};
+
+// one bucket for the whole process, every worker and every request take from it
+static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+pub struct RateLimiter {
+    default_rate: Option<u64>,
+    schedule: Vec<ScheduleRule>,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct ScheduleRule {
+    start: NaiveTime,
+    end: NaiveTime,
+    rate: Option<u64>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+pub fn init(limit_rate: &Option<String>, limit_schedule: &Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let default_rate = match limit_rate {
+        Some(rate) => parse_rate(rate)?,
+        None => None,
+    };
+    let schedule = match limit_schedule {
+        Some(path) => parse_schedule(&fs::read_to_string(path)?)?,
+        None => vec![],
+    };
+
+    if default_rate.is_none() && schedule.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(rate) = default_rate {
+        info!("Bandwidth limited to {}/s", human_rate(rate));
+    }
+    for rule in &schedule {
+        info!(
+            "Bandwidth between {} and {}: {}",
+            rule.start.format("%H:%M"),
+            rule.end.format("%H:%M"),
+            rule.rate.map(|r| format!("{}/s", human_rate(r))).unwrap_or("unlimited".to_string())
+        );
+    }
+
+    let _ = LIMITER.set(RateLimiter {
+        default_rate,
+        schedule,
+        bucket: Mutex::new(Bucket {
+            tokens: 0.0,
+            last: Instant::now(),
+        }),
+    });
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    LIMITER.get().is_some()
+}
+
+// wait until `bytes` can be spent, the bucket can go in debt so big chunk only wait longer
+pub async fn throttle(bytes: usize) {
+    let Some(limiter) = LIMITER.get() else {
+        return;
+    };
+    let Some(rate) = limiter.current_rate() else {
+        return;
+    };
+
+    let wait = limiter.bucket.lock().unwrap().take(bytes, rate, Instant::now());
+
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+impl Bucket {
+    // how long to wait before `bytes` are spent
+    fn take(&mut self, bytes: usize, rate: u64, now: Instant) -> Duration {
+        let refill = now.duration_since(self.last).as_secs_f64() * rate as f64;
+        // allow at most one second of burst
+        self.tokens = (self.tokens + refill).min(rate as f64);
+        self.last = now;
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / rate as f64)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+impl RateLimiter {
+    fn current_rate(&self) -> Option<u64> {
+        self.rate_at(Local::now().time().with_nanosecond(0).unwrap_or_default())
+    }
+
+    fn rate_at(&self, now: NaiveTime) -> Option<u64> {
+        self.schedule
+            .iter()
+            .find(|rule| rule.contains(now))
+            .map(|rule| rule.rate)
+            .unwrap_or(self.default_rate)
+    }
+}
+
+impl ScheduleRule {
+    fn contains(&self, now: NaiveTime) -> bool {
+        if self.start <= self.end {
+            now >= self.start && now < self.end
+        } else {
+            // cross midnight like 22:00-06:00
+            now >= self.start || now < self.end
+        }
+    }
+}
+
+// "500K", "5M", "1G" bytes per second like curl, "0" mean no limit
+pub fn parse_rate(rate: &str) -> Result<Option<u64>, Box<dyn Error>> {
+    let rate = rate.trim();
+    let (number, unit) = rate.split_at(rate.find(|c: char| c.is_alphabetic()).unwrap_or(rate.len()));
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid rate \"{rate}\" use something like 500K, 5M or 1G"))?;
+    let multiplier = match unit.to_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1024.0,
+        "M" | "MB" => 1024.0 * 1024.0,
+        "G" | "GB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("Invalid rate unit \"{unit}\" use K, M or G").into()),
+    };
+    let bytes = (number * multiplier) as u64;
+    Ok(if bytes == 0 { None } else { Some(bytes) })
+}
+
+// one rule by line "08:00-18:00 2M", empty line and # comment are ignored
+fn parse_schedule(content: &str) -> Result<Vec<ScheduleRule>, Box<dyn Error>> {
+    let mut schedule = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((range, rate)) = line.split_once(char::is_whitespace) else {
+            return Err(format!("limit schedule line {}: expected \"HH:MM-HH:MM RATE\"", number + 1).into());
+        };
+        let Some((start, end)) = range.split_once('-') else {
+            return Err(format!("limit schedule line {}: expected \"HH:MM-HH:MM\"", number + 1).into());
+        };
+        schedule.push(ScheduleRule {
+            start: NaiveTime::parse_from_str(start, "%H:%M")?,
+            end: NaiveTime::parse_from_str(end, "%H:%M")?,
+            rate: parse_rate(rate)?,
+        });
+    }
+    if schedule.is_empty() {
+        warn!("limit schedule is empty, ignored");
+    }
+    Ok(schedule)
+}
+
+fn human_rate(rate: u64) -> String {
+    match rate {
+        r if r >= 1024 * 1024 * 1024 => format!("{:.1}G", r as f64 / (1024.0 * 1024.0 * 1024.0)),
+        r if r >= 1024 * 1024 => format!("{:.1}M", r as f64 / (1024.0 * 1024.0)),
+        r if r >= 1024 => format!("{:.1}K", r as f64 / 1024.0),
+        r => format!("{r}B"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEDULE: &str = "# weekdays\n08:00-18:00 500K\n\n22:00-06:00 0 # night unlimited\n";
+
+    fn time(t: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(t, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn rate_read_suffixes_like_curl() {
+        assert_eq!(parse_rate("500").unwrap(), Some(500));
+        assert_eq!(parse_rate("500K").unwrap(), Some(500 * 1024));
+        assert_eq!(parse_rate(" 5mb ").unwrap(), Some(5 * 1024 * 1024));
+        assert_eq!(parse_rate("1.5G").unwrap(), Some(1024 * 1024 * 1024 * 3 / 2));
+        assert_eq!(parse_rate("0").unwrap(), None);
+    }
+
+    #[test]
+    fn bad_rates_are_refused() {
+        assert!(parse_rate("").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("5T").unwrap_err().to_string().contains("unit \"T\""));
+        assert!(parse_rate("-K").is_err());
+    }
+
+    #[test]
+    fn schedule_skip_comments_and_empty_lines() {
+        let schedule = parse_schedule(SCHEDULE).unwrap();
+        assert_eq!(schedule.len(), 2);
+        assert_eq!((schedule[0].start, schedule[0].end, schedule[0].rate), (time("08:00"), time("18:00"), Some(500 * 1024)));
+        assert_eq!((schedule[1].start, schedule[1].end, schedule[1].rate), (time("22:00"), time("06:00"), None));
+    }
+
+    #[test]
+    fn bad_schedule_lines_are_refused() {
+        let e = parse_schedule("08:00-18:00 1M\n08:00 1M\n").unwrap_err();
+        assert!(e.to_string().starts_with("limit schedule line 2"), "{e}");
+        assert!(parse_schedule("08:00-18:00\n").is_err());
+        assert!(parse_schedule("8h-18h 1M\n").is_err());
+        assert!(parse_schedule("25:00-18:00 1M\n").is_err());
+    }
+
+    #[test]
+    fn a_window_can_cross_midnight() {
+        let night = ScheduleRule {
+            start: time("22:00"),
+            end: time("06:00"),
+            rate: None,
+        };
+        assert!(night.contains(time("23:30")));
+        assert!(night.contains(time("00:00")));
+        assert!(night.contains(time("05:59")));
+        assert!(!night.contains(time("06:00")));
+        assert!(!night.contains(time("12:00")));
+        assert!(!night.contains(time("21:59")));
+    }
+
+    #[test]
+    fn the_first_matching_rule_win_then_the_default_rate() {
+        let limiter = RateLimiter {
+            default_rate: Some(2 * 1024 * 1024),
+            schedule: parse_schedule(SCHEDULE).unwrap(),
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last: Instant::now(),
+            }),
+        };
+        assert_eq!(limiter.rate_at(time("09:00")), Some(500 * 1024));
+        assert_eq!(limiter.rate_at(time("03:00")), None);
+        assert_eq!(limiter.rate_at(time("19:00")), Some(2 * 1024 * 1024));
+    }
+
+    #[test]
+    fn bucket_refill_with_time() {
+        let start = Instant::now();
+        let mut bucket = Bucket { tokens: 0.0, last: start };
+
+        // 1000 bytes at 1000/s from an empty bucket: one second of debt
+        assert_eq!(bucket.take(1000, 1000, start), Duration::from_secs(1));
+        // paid back after a second
+        assert_eq!(bucket.take(0, 1000, start + Duration::from_secs(1)), Duration::ZERO);
+        assert_eq!(bucket.take(500, 1000, start + Duration::from_millis(1500)), Duration::ZERO);
+        assert_eq!(bucket.take(500, 1000, start + Duration::from_millis(1500)), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn burst_is_one_second_at_most() {
+        let start = Instant::now();
+        let mut bucket = Bucket { tokens: 0.0, last: start };
+
+        // a minute idle only give one second of tokens
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.take(1000, 1000, later), Duration::ZERO);
+        assert_eq!(bucket.take(1000, 1000, later), Duration::from_secs(1));
+    }
+}
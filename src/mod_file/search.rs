@@ -4,7 +4,7 @@ use reqwest::Client;
 use serde_derive::{Deserialize, Serialize};
 use tokio::time;
 
-use crate::{debug, mod_file::web, warn};
+use crate::{debug, info, mod_file::cmd_line_parser::Args, mod_file::search_filter, mod_file::search_index, warn};
 
 #[derive(Clone, Debug, Default)]
 pub struct ProcessingUrl {
@@ -12,59 +12,53 @@ pub struct ProcessingUrl {
     pub ep: String,
     pub url: String,
     pub genre: String,
+    // alias the keyword matched when it isn't the title
+    pub matched: Option<String>,
+    // relevance from 0 to 1, None without keyword
+    pub score: Option<f64>,
+}
+
+impl ProcessingUrl {
+    // "92% Title aka "alias"" as shown in the prompt and in errors
+    pub fn label(&self) -> String {
+        let mut label = self.name.clone();
+        if let Some(score) = self.score {
+            label = format!("{:>3}% {label}", percent(score));
+        }
+        if let Some(alias) = &self.matched {
+            label = format!("{label} aka \"{alias}\"");
+        }
+        label
+    }
 }
 
 pub async fn search_over_json(
+    base_url: &str,
     name: &str,
-    lang: &str,
-    debug: &bool,
+    args: &Args,
 ) -> Result<Vec<ProcessingUrl>, Box<dyn Error>> {
-    let mut edit_lang = lang.to_lowercase();
+    let mut edit_lang = args.language.to_lowercase();
     if edit_lang != "vf".to_string() && edit_lang != "vostfr".to_string() {
         warn!("\"{edit_lang}\" doesn't exist, replaced by \"vf\" automatically, use only \"vf\" or \"vostfr\"");
         edit_lang = "vf".to_string();
     }
 
     let client = Client::builder().build()?;
-    let base_url = "https://neko-sama.fr";
-    let mut find = vec![];
-    let resp = web::web_request(
+    let rep = search_index::load(
         &client,
         &format!("{}/animes-search-{}.json", base_url, edit_lang),
+        &search_index::cache_dir()?,
+        args,
     )
-        .await
-        .unwrap();
-
-    let rep = resp.text().await?;
-    let cleaned_name = clean_string(name);
+        .await?;
 
     let v = serde_json::from_str::<Root>(&rep)?;
-
-    for x in v {
-        let cleaned_title = clean_string(&x.title);
-
-        let levenshtein_distance = strsim::levenshtein(&cleaned_name, &cleaned_title) as f64;
-        let max_length = cleaned_name.len().max(cleaned_title.len()) as f64;
-        let levenshtein_similarity = 1.0 - levenshtein_distance / max_length;
-
-        if jaccard_similarity(&cleaned_name, &cleaned_title) > 0.8
-            || levenshtein_similarity > 0.8
-            || cleaned_title.contains(&cleaned_name)
-        {
-            let x = ProcessingUrl {
-                name: x.title,
-                ep: x.nb_eps,
-                url: format!("{}{}", base_url, x.url),
-                genre: x.genres.join(", ").replace("c0m1dy", "comedy"),
-            };
-            if *debug {
-                debug!("Search engine {:#?}", x);
-            }
-            find.push(x);
-        }
-    }
-    if find.len() == 0 {
+    let find = rank(v, name, args, base_url);
+    if find.is_empty() {
         warn!("Noting found retry with another keyword");
+        if search_filter::is_set(args) {
+            warn!("Or with less filters ({})", search_filter::describe(args));
+        }
         warn!("Or try with -l vostfr or -l vf (vf is used by default)");
         time::sleep(Duration::from_secs(20)).await;
         exit(130);
@@ -72,23 +66,126 @@ pub async fn search_over_json(
     Ok(find)
 }
 
-fn clean_string(s: &str) -> String {
+// seasons left by the filters, most relevant first, the most popular season win a tie
+pub(crate) fn rank(seasons: Root, name: &str, args: &Args, base_url: &str) -> Vec<ProcessingUrl> {
+    let cleaned_name = clean_string(name).trim().to_string();
+    let mut find: Vec<(f64, f64, ProcessingUrl)> = vec![];
+
+    for x in seasons {
+        if !search_filter::matches(&x, args) {
+            continue;
+        }
+        // browsing with filters only, every season left match and popularity decide the order
+        if cleaned_name.is_empty() {
+            find.push((0.0, x.popularity, to_processing_url(&x, None, None, base_url)));
+            continue;
+        }
+
+        // best alias of the season, the title win a tie
+        let (score, alias) = x
+            .aliases()
+            .into_iter()
+            .map(|alias| (relevance(&cleaned_name, clean_string(alias).trim()), alias))
+            .fold((0.0, x.title.as_str()), |best, (s, alias)| if s > best.0 { (s, alias) } else { best });
+        if score < MIN_RELEVANCE {
+            continue;
+        }
+
+        let p = to_processing_url(&x, (alias != x.title).then(|| alias.to_string()), Some(score), base_url);
+        if args.debug {
+            debug!("Search engine {:#?}", p);
+        }
+        find.push((score, x.popularity, p));
+    }
+    // scores are compared as shown
+    find.sort_by(|a, b| {
+        percent(b.0)
+            .cmp(&percent(a.0))
+            .then(b.1.total_cmp(&a.1))
+    });
+    if let Some(limit) = args.limit {
+        if find.len() > limit {
+            info!("{} seasons found, only the {} first are shown ( --limit )", find.len(), limit);
+            find.truncate(limit);
+        }
+    }
+    find.into_iter().map(|(_, _, p)| p).collect()
+}
+
+fn to_processing_url(season: &Season, matched: Option<String>, score: Option<f64>, base_url: &str) -> ProcessingUrl {
+    ProcessingUrl {
+        name: season.title.clone(),
+        ep: season.nb_eps.clone(),
+        url: format!("{}{}", base_url, season.url),
+        genre: season.genres.join(", ").replace("c0m1dy", "comedy"),
+        matched,
+        score,
+    }
+}
+
+// below this a season isn't shown
+const MIN_RELEVANCE: f64 = 0.45;
+
+pub fn percent(score: f64) -> u32 {
+    (score * 100.0).round() as u32
+}
+
+// 0 to 1, string similarity count for 75%, starting like the keyword 15% and being the keyword 10%
+fn relevance(cleaned_name: &str, cleaned_alias: &str) -> f64 {
+    if cleaned_alias.is_empty() || cleaned_name.is_empty() {
+        return 0.0;
+    }
+    let levenshtein_distance = strsim::levenshtein(cleaned_name, cleaned_alias) as f64;
+    let max_length = cleaned_name.len().max(cleaned_alias.len()) as f64;
+    let levenshtein_similarity = 1.0 - levenshtein_distance / max_length;
+
+    // "shingeki" inside "shingeki no kyojin", better the more of the alias it cover
+    let coverage = if cleaned_alias.contains(cleaned_name) {
+        0.8 + 0.2 * cleaned_name.len() as f64 / cleaned_alias.len() as f64
+    } else {
+        0.0
+    };
+
+    // every word of the keyword in the alias, whatever the order
+    let alias_words: Vec<&str> = cleaned_alias.split_whitespace().collect();
+    let name_words: Vec<&str> = cleaned_name.split_whitespace().collect();
+    let found = name_words.iter().filter(|w| alias_words.contains(w)).count() as f64;
+    let word_overlap = 0.8 * found / name_words.len() as f64;
+
+    let similarity = levenshtein_similarity.max(coverage).max(word_overlap);
+    let prefix = if cleaned_alias.starts_with(cleaned_name) { 0.15 } else { 0.0 };
+    let exact = if cleaned_alias == cleaned_name { 0.1 } else { 0.0 };
+    similarity * 0.75 + prefix + exact
+}
+
+pub fn clean_string(s: &str) -> String {
     s.chars()
         .filter(|&c| c.is_alphanumeric() || c.is_whitespace())
         .collect::<String>()
         .to_lowercase()
 }
 
-fn jaccard_similarity(s1: &str, s2: &str) -> f64 {
-    let set1: std::collections::HashSet<_> = s1.chars().collect();
-    let set2: std::collections::HashSet<_> = s2.chars().collect();
-    let intersection_size = set1.intersection(&set2).count() as f64;
-    let union_size = set1.union(&set2).count() as f64;
-    intersection_size / union_size
-}
-
 pub type Root = Vec<Season>;
 
+impl Season {
+    // title first, `others` is a comma separated list
+    pub fn aliases(&self) -> Vec<&str> {
+        let mut aliases = vec![self.title.as_str()];
+        for alias in [&self.title_english, &self.title_romanji, &self.title_french]
+            .into_iter()
+            .flatten()
+            .map(|a| a.as_str())
+            .chain(self.others.split(','))
+            .map(|a| a.trim())
+        {
+            if !alias.is_empty() && !aliases.contains(&alias) {
+                aliases.push(alias);
+            }
+        }
+        aliases
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Season {
@@ -115,3 +212,125 @@ pub struct Season {
     #[serde(rename = "nb_eps")]
     pub nb_eps: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn season(id: i64, title: &str, popularity: f64) -> Season {
+        Season {
+            id,
+            title: title.to_string(),
+            popularity,
+            url: format!("/anime/info/{id}"),
+            ..Default::default()
+        }
+    }
+
+    fn seasons() -> Root {
+        vec![
+            season(1, "Kimetsu no Yaiba Movie: Mugen Ressha-hen", 90.0),
+            season(2, "Kimetsu Gakuen: Valentine-hen", 10.0),
+            season(3, "Kimetsu no Yaiba", 50.0),
+            season(4, "Kimetsu no Yaiba: Katanakaji no Sato-hen", 80.0),
+            season(5, "One Piece", 99.0),
+        ]
+    }
+
+    fn names(found: &[ProcessingUrl]) -> Vec<&str> {
+        found.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    fn args(extra: &[&str]) -> Args {
+        Args::parse_from(["neko_dl"].iter().chain(extra))
+    }
+
+    #[test]
+    fn the_exact_title_come_first() {
+        let found = rank(seasons(), "Kimetsu no Yaiba", &args(&[]), "https://neko-sama.fr");
+        assert_eq!(found[0].name, "Kimetsu no Yaiba");
+        assert_eq!(found[0].score.map(percent), Some(100));
+        assert_eq!(found[0].url, "https://neko-sama.fr/anime/info/3");
+        assert!(found.iter().skip(1).all(|p| p.score < found[0].score));
+    }
+
+    #[test]
+    fn loosely_related_seasons_are_dropped() {
+        let found = rank(seasons(), "kimetsu no yaiba", &args(&[]), "");
+        assert!(!names(&found).contains(&"One Piece"));
+        assert!(found.iter().all(|p| p.score.unwrap() >= MIN_RELEVANCE));
+        assert!(relevance("kimetsu no yaiba", "one piece") < MIN_RELEVANCE);
+        assert!(rank(seasons(), "naruto", &args(&[]), "").is_empty());
+    }
+
+    #[test]
+    fn popularity_break_ties() {
+        let seasons = vec![
+            season(1, "Haikyuu!!", 40.0),
+            season(2, "Haikyuu", 70.0),
+        ];
+        // both clean to "haikyuu"
+        assert_eq!(names(&rank(seasons, "haikyuu", &args(&[]), "")), ["Haikyuu", "Haikyuu!!"]);
+    }
+
+    #[test]
+    fn without_keyword_the_most_popular_come_first() {
+        let found = rank(seasons(), "", &args(&[]), "");
+        assert_eq!(found.len(), 5);
+        assert_eq!(found[0].name, "One Piece");
+        assert!(found.iter().all(|p| p.score.is_none()));
+    }
+
+    #[test]
+    fn limit_keep_the_best_ones() {
+        let found = rank(seasons(), "kimetsu no yaiba", &args(&["--limit", "2"]), "");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].name, "Kimetsu no Yaiba");
+        assert_eq!(names(&rank(seasons(), "kimetsu no yaiba", &args(&[]), ""))[..2], names(&found)[..]);
+    }
+
+    #[test]
+    fn relevance_reward_prefix_and_exact_match() {
+        let exact = relevance("shingeki no kyojin", "shingeki no kyojin");
+        let prefix = relevance("shingeki", "shingeki no kyojin");
+        let inside = relevance("kyojin", "shingeki no kyojin");
+        assert!((exact - 1.0).abs() < 1e-9);
+        assert!(exact > prefix && prefix > inside, "{exact} {prefix} {inside}");
+        assert_eq!(relevance("", "shingeki"), 0.0);
+        assert_eq!(relevance("shingeki", ""), 0.0);
+    }
+
+    #[test]
+    fn an_english_title_find_the_season() {
+        let mut shingeki = season(1, "Shingeki no Kyojin", 20.0);
+        shingeki.title_english = Some("Attack on Titan".to_string());
+        shingeki.others = "L'Attaque des Titans, AoT".to_string();
+        let found = rank(vec![shingeki, season(2, "Kimetsu no Yaiba", 90.0)], "attack on titan", &args(&[]), "");
+        assert_eq!(names(&found), ["Shingeki no Kyojin"]);
+        assert_eq!(found[0].matched.as_deref(), Some("Attack on Titan"));
+        assert_eq!(found[0].label(), "100% Shingeki no Kyojin aka \"Attack on Titan\"");
+
+        // same name as the title, the title is reported
+        let mut shingeki = season(1, "Shingeki no Kyojin", 20.0);
+        shingeki.title_romanji = Some("Shingeki no Kyojin".to_string());
+        let found = rank(vec![shingeki], "shingeki no kyojin", &args(&[]), "");
+        assert_eq!(found[0].matched, None);
+    }
+
+    #[test]
+    fn blank_aliases_are_skipped() {
+        let mut x = season(1, "Kimetsu no Yaiba", 20.0);
+        x.title_english = Some("".to_string());
+        x.title_romanji = Some("  ".to_string());
+        x.title_french = None;
+        x.others = " , Demon Slayer,, Kimetsu no Yaiba , ".to_string();
+        assert_eq!(x.aliases(), ["Kimetsu no Yaiba", "Demon Slayer"]);
+
+        x.others = " , ".to_string();
+        assert_eq!(x.aliases(), ["Kimetsu no Yaiba"]);
+        // an empty keyword isn't matched against blank aliases
+        assert!(rank(vec![x], "  ", &args(&[]), "")[0].matched.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use crate::mod_file::{
+    cmd_line_parser::{Args, SeasonStatus},
+    search::Season,
+};
+
+// --year 2015, 2015..2020, 2015.. or ..2020, both ends included
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct YearRange {
+    pub from: Option<u32>,
+    pub to: Option<u32>,
+}
+
+impl YearRange {
+    pub fn contains(&self, year: u32) -> bool {
+        self.from.is_none_or(|from| year >= from) && self.to.is_none_or(|to| year <= to)
+    }
+}
+
+impl FromStr for YearRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<YearRange, String> {
+        let s = s.trim();
+        let invalid = || format!("Invalid year \"{s}\" use 2015, 2015..2020, 2015.. or ..2020");
+        let year = |y: &str| -> Result<Option<u32>, String> {
+            match y.trim() {
+                "" => Ok(None),
+                y => y.parse().map(Some).map_err(|_| invalid()),
+            }
+        };
+        let range = match s.split_once("..") {
+            Some((from, to)) => YearRange { from: year(from)?, to: year(to)? },
+            None => {
+                let y = year(s)?.ok_or_else(invalid)?;
+                YearRange { from: Some(y), to: Some(y) }
+            }
+        };
+        match (range.from, range.to) {
+            (None, None) => Err(invalid()),
+            (Some(from), Some(to)) if from > to => Err(invalid()),
+            _ => Ok(range),
+        }
+    }
+}
+
+impl Display for YearRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if from == to => write!(f, "{from}"),
+            (from, to) => write!(
+                f,
+                "{}..{}",
+                from.map(|y| y.to_string()).unwrap_or_default(),
+                to.map(|y| y.to_string()).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+// some words of the index are written in leet, "c0m1dy" or "m0v1e"
+pub fn normalize(s: &str) -> String {
+    s.trim()
+        .to_lowercase()
+        .replace("c0m1dy", "comedy")
+        .replace("m0v1e", "movie")
+}
+
+// a search without keyword list the whole catalogue, only if something narrow it
+pub fn is_set(args: &Args) -> bool {
+    !args.season_type.is_empty()
+        || args.status.is_some()
+        || !args.genre.is_empty()
+        || args.year.is_some()
+        || args.min_score.is_some()
+}
+
+// every filter given must match, a value missing from the index never match
+pub fn matches(season: &Season, args: &Args) -> bool {
+    if !args.season_type.is_empty() {
+        let season_type = normalize(&season.type_field);
+        if !args.season_type.iter().any(|t| normalize(t) == season_type) {
+            return false;
+        }
+    }
+
+    if let Some(status) = args.status {
+        // the index use "1" for airing and "2" for finished
+        let airing = match normalize(&season.status).as_str() {
+            "1" | "airing" | "en cours" => true,
+            "2" | "finished" | "terminé" => false,
+            _ => return false,
+        };
+        if airing != (status == SeasonStatus::Airing) {
+            return false;
+        }
+    }
+
+    if !args.genre.is_empty() {
+        let genres: Vec<String> = season.genres.iter().map(|g| normalize(g)).collect();
+        if !args.genre.iter().all(|g| genres.contains(&normalize(g))) {
+            return false;
+        }
+    }
+
+    if let Some(year) = args.year {
+        match season.start_date_year.trim().parse::<u32>() {
+            Ok(start) if year.contains(start) => {}
+            _ => return false,
+        }
+    }
+
+    if let Some(min_score) = args.min_score {
+        match season.score.trim().parse::<f64>() {
+            Ok(score) if score >= min_score => {}
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+// shown in the config and when nothing is found
+pub fn describe(args: &Args) -> String {
+    let mut filters = vec![];
+    if !args.season_type.is_empty() {
+        filters.push(format!("type {}", args.season_type.join("|")));
+    }
+    if let Some(status) = args.status {
+        filters.push(format!("status {status:?}").to_lowercase());
+    }
+    if !args.genre.is_empty() {
+        filters.push(format!("genre {}", args.genre.join(",")));
+    }
+    if let Some(year) = args.year {
+        filters.push(format!("year {year}"));
+    }
+    if let Some(min_score) = args.min_score {
+        filters.push(format!("score >= {min_score}"));
+    }
+    if filters.is_empty() {
+        "none".to_string()
+    } else {
+        filters.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::mod_file::search::{rank, Root};
+
+    fn index() -> Root {
+        serde_json::from_str(include_str!("../../tests/fixtures/search/animes-search-vostfr.json")).unwrap()
+    }
+
+    fn found(keyword: &str, filters: &[&str]) -> Vec<String> {
+        let args = Args::parse_from(["neko_dl"].iter().chain(filters));
+        rank(index(), keyword, &args, "").into_iter().map(|p| p.name).collect()
+    }
+
+    #[test]
+    fn filters_alone_list_the_matching_seasons_by_popularity() {
+        assert_eq!(found("", &["--type", "tv", "--status", "airing"]), ["One Piece", "Spy x Family"]);
+        assert_eq!(
+            found("", &["--genre", "comedy,action", "--year", "2000.."]),
+            ["Spy x Family"]
+        );
+        assert_eq!(
+            found("", &["--type", "movie,ova", "--min-score", "7.5"]),
+            ["Spy x Family: Extra Mission"]
+        );
+        assert_eq!(found("", &["--status", "finished", "--year", "..2015", "--genre", "FANTASY"]), ["Shingeki no Kyojin"]);
+    }
+
+    #[test]
+    fn filters_narrow_a_keyword_search() {
+        let all = found("shingeki", &[]);
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0], "Shingeki no Kyojin");
+
+        // the shorter title is closer to the keyword
+        assert_eq!(
+            found("shingeki", &["--type", "tv", "--year", "2014..2020"]),
+            ["Shingeki! Kyojin Chuugakkou", "Shingeki no Kyojin Season 2"]
+        );
+        assert_eq!(found("attack on titan", &["--type", "movie"]), ["Shingeki no Kyojin: Chronicle"]);
+        assert_eq!(found("shingeki", &["--genre", "comedy"]), ["Shingeki! Kyojin Chuugakkou"]);
+        assert!(found("shingeki", &["--status", "airing"]).is_empty());
+    }
+
+    #[test]
+    fn a_missing_year_or_score_never_match() {
+        assert_eq!(found("demon slayer", &[]), ["Kimetsu no Yaiba"]);
+        assert!(found("demon slayer", &["--year", "2019"]).is_empty());
+        assert!(found("demon slayer", &["--min-score", "1"]).is_empty());
+    }
+
+    #[test]
+    fn year_range_parse_and_show() {
+        let range = |s: &str| s.parse::<YearRange>();
+        assert_eq!(range("2015"), Ok(YearRange { from: Some(2015), to: Some(2015) }));
+        assert_eq!(range(" 2015..2020 ").unwrap().to_string(), "2015..2020");
+        assert_eq!(range("2015..").unwrap().to_string(), "2015..");
+        assert!(range("..2020").unwrap().contains(1999));
+        assert!(!range("..2020").unwrap().contains(2021));
+        assert!(range("..").is_err());
+        assert!(range("2020..2015").is_err());
+        assert!(range("twenty").is_err());
+    }
+
+    #[test]
+    fn describe_list_the_filters_given() {
+        let args = Args::parse_from(["neko_dl", "--type", "tv,ova", "--status", "airing", "--year", "2015..", "--min-score", "7.5"]);
+        assert!(is_set(&args));
+        assert_eq!(describe(&args), "type tv|ova, status airing, year 2015.., score >= 7.5");
+        let args = Args::parse_from(["neko_dl"]);
+        assert!(!is_set(&args));
+        assert_eq!(describe(&args), "none");
+    }
+}
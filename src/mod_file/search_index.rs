@@ -0,0 +1,258 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, StatusCode, Url,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{debug, warn};
+use crate::mod_file::{cmd_line_parser::Args, web};
+
+// how the cached index was downloaded, next to it in <index>.meta.json
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IndexMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    // unix seconds of the last download or revalidation
+    checked_at: u64,
+}
+
+// utils/cache next to the executable, a saved index can be dropped here to search offline
+pub fn cache_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let binding = env::current_exe()?;
+    Ok(binding.parent().unwrap().join("utils/cache"))
+}
+
+// <cache_dir>/<host>/animes-search-<lang>.json
+fn cache_file(cache_dir: &Path, url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let parsed = Url::parse(url)?;
+    let name = parsed
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("index.json");
+    Ok(cache_dir.join(parsed.host_str().unwrap_or_default()).join(name))
+}
+
+// fresh copy under --index-ttl hours, else revalidated with ETag / Last-Modified, the stale copy is used when offline
+pub async fn load(client: &Client, url: &str, cache_dir: &Path, args: &Args) -> Result<String, Box<dyn Error>> {
+    let path = cache_file(cache_dir, url)?;
+    let meta_path = path.with_extension("meta.json");
+    let cached = fs::read_to_string(&path).ok();
+    let meta = fs::read_to_string(&meta_path)
+        .ok()
+        .and_then(|m| serde_json::from_str::<IndexMeta>(&m).ok())
+        .filter(|m| m.url == url);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    // a file copied by hand has no meta, its modification time is used
+    let checked_at = meta.as_ref().map(|m| m.checked_at).or_else(|| {
+        fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    });
+    let age = checked_at.map(|t| Duration::from_secs(now.saturating_sub(t)));
+
+    if let (Some(index), Some(age)) = (&cached, age) {
+        if !args.refresh_index && age < Duration::from_secs(args.index_ttl * 3600) {
+            if args.debug {
+                debug!("search index from cache {} ({}s old)", path.display(), age.as_secs());
+            }
+            return Ok(index.clone());
+        }
+    }
+
+    // --refresh-index download it again even if the server say it didn't change
+    let mut headers = vec![];
+    if let (Some(_), Some(meta), false) = (&cached, &meta, args.refresh_index) {
+        if let Some(etag) = &meta.etag {
+            headers.push((IF_NONE_MATCH.to_string(), etag.clone()));
+        }
+        if let Some(last_modified) = &meta.last_modified {
+            headers.push((IF_MODIFIED_SINCE.to_string(), last_modified.clone()));
+        }
+    }
+
+    let fetched = match web::web_request_with_headers(client, url, &headers).await {
+        Ok(resp) if resp.status() == StatusCode::NOT_MODIFIED && cached.is_some() => {
+            if args.debug {
+                debug!("search index not modified since last download");
+            }
+            let meta = IndexMeta {
+                url: url.to_string(),
+                checked_at: now,
+                ..meta.unwrap_or_default()
+            };
+            save_meta(&meta_path, &meta);
+            return Ok(cached.unwrap_or_default());
+        }
+        Ok(resp) if resp.status() == StatusCode::OK => {
+            let header = |name| {
+                resp.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string())
+            };
+            let meta = IndexMeta {
+                url: url.to_string(),
+                etag: header(ETAG),
+                last_modified: header(LAST_MODIFIED),
+                checked_at: now,
+            };
+            web::read_text(resp)
+                .await
+                .map(|index| (index, meta))
+                .map_err(|e| e.to_string())
+        }
+        Ok(resp) => Err(resp.status().to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    match (fetched, cached) {
+        (Ok((index, meta)), _) => {
+            if let Err(e) = save(&path, &index) {
+                warn!("Can't cache search index in {}: {}", path.display(), e);
+            } else {
+                save_meta(&meta_path, &meta);
+            }
+            Ok(index)
+        }
+        (Err(e), Some(index)) => {
+            warn!(
+                "Can't download search index ({}), using the copy saved {}h ago",
+                e,
+                age.unwrap_or_default().as_secs() / 3600
+            );
+            Ok(index)
+        }
+        (Err(e), None) => Err(format!("Can't download search index {url}: {e}").into()),
+    }
+}
+
+// write then rename, an interrupted download never leave half an index
+fn save(path: &PathBuf, index: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("part");
+    fs::write(&tmp, index)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn save_meta(meta_path: &PathBuf, meta: &IndexMeta) {
+    if let Ok(json) = serde_json::to_string(meta) {
+        if let Err(e) = fs::write(meta_path, json) {
+            warn!("Can't write {}: {}", meta_path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::mod_file::test_server::{temp_dir, Reply, TestServer};
+
+    const INDEX: &str = r#"[{"id":1,"title":"Kimetsu no Yaiba"}]"#;
+
+    fn args(extra: &[&str]) -> Args {
+        Args::parse_from(["neko_dl"].iter().chain(extra))
+    }
+
+    // answer 304 when the client already has the ETag
+    fn index_server() -> TestServer {
+        TestServer::start(|req| match req.headers.get("if-none-match").map(|e| e.as_str()) {
+            Some("\"v1\"") => Reply::status(304),
+            _ => Reply::ok(INDEX)
+                .header("ETag", "\"v1\"")
+                .header("Last-Modified", "Wed, 10 Jan 2024 09:00:00 GMT"),
+        })
+    }
+
+    #[test]
+    fn cache_file_is_named_after_the_host_and_the_index() {
+        let dir = Path::new("/cache");
+        assert_eq!(
+            cache_file(dir, "https://neko-sama.fr/animes-search-vf.json").unwrap(),
+            dir.join("neko-sama.fr").join("animes-search-vf.json")
+        );
+        assert_eq!(cache_file(dir, "https://neko-sama.fr/").unwrap(), dir.join("neko-sama.fr").join("index.json"));
+    }
+
+    #[tokio::test]
+    async fn a_fresh_copy_is_used_without_asking_the_site() {
+        let dir = temp_dir("index-fresh");
+        let server = index_server();
+        let url = server.url("/animes-search-vf.json");
+
+        assert_eq!(load(&Client::new(), &url, &dir, &args(&[])).await.unwrap(), INDEX);
+        assert_eq!(load(&Client::new(), &url, &dir, &args(&[])).await.unwrap(), INDEX);
+        assert_eq!(server.requests("/animes-search-vf.json").len(), 1);
+
+        let meta: IndexMeta = serde_json::from_str(
+            &fs::read_to_string(cache_file(&dir, &url).unwrap().with_extension("meta.json")).unwrap(),
+        )
+            .unwrap();
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(meta.last_modified.as_deref(), Some("Wed, 10 Jan 2024 09:00:00 GMT"));
+    }
+
+    #[tokio::test]
+    async fn an_old_copy_is_revalidated() {
+        let dir = temp_dir("index-revalidate");
+        let server = index_server();
+        let url = server.url("/animes-search-vf.json");
+
+        load(&Client::new(), &url, &dir, &args(&[])).await.unwrap();
+        assert_eq!(load(&Client::new(), &url, &dir, &args(&["--index-ttl", "0"])).await.unwrap(), INDEX);
+
+        let requests = server.requests("/animes-search-vf.json");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].headers.get("if-none-match").map(|e| e.as_str()), Some("\"v1\""));
+        assert_eq!(
+            requests[1].headers.get("if-modified-since").map(|e| e.as_str()),
+            Some("Wed, 10 Jan 2024 09:00:00 GMT")
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_ask_the_whole_index_again() {
+        let dir = temp_dir("index-refresh");
+        let server = index_server();
+        let url = server.url("/animes-search-vf.json");
+
+        load(&Client::new(), &url, &dir, &args(&[])).await.unwrap();
+        load(&Client::new(), &url, &dir, &args(&["--refresh-index"])).await.unwrap();
+
+        let requests = server.requests("/animes-search-vf.json");
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[1].headers.contains_key("if-none-match"));
+    }
+
+    #[tokio::test]
+    async fn the_saved_copy_is_used_when_the_site_fail() {
+        let dir = temp_dir("index-offline");
+        let server = TestServer::start(|_| Reply::status(404));
+        let url = server.url("/animes-search-vf.json");
+
+        assert!(load(&Client::new(), &url, &dir, &args(&["--index-ttl", "0"])).await.is_err());
+
+        // a copy dropped by hand, without meta
+        let path = cache_file(&dir, &url).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, INDEX).unwrap();
+        assert_eq!(load(&Client::new(), &url, &dir, &args(&["--index-ttl", "0"])).await.unwrap(), INDEX);
+    }
+}
@@ -1,23 +1,42 @@
 pub const BASE_URL: &str = "https://neko-sama.fr";
 
-// chrome driver
+// chrome driver, downloaded from chrome for testing to match the installed chrome
+pub const CHROMEDRIVER_INDEX: &str =
+    "https://googlechromelabs.github.io/chrome-for-testing/known-good-versions-with-downloads.json";
+
+pub const CHROME_FOR_TESTING: &str = "https://googlechromelabs.github.io/chrome-for-testing/";
+
 #[cfg(target_os = "macos")]
 #[cfg(target_arch = "x86_64")]
-pub const DRIVER_PATH: &str =
-    "https://edgedl.me.gvt1.com/edgedl/chrome/chrome-for-testing/120.0.6099.71/mac-x64/chromedriver-mac-x64.zip";
+pub const DRIVER_PLATFORM: &str = "mac-x64";
 
 #[cfg(target_os = "macos")]
-#[cfg(target_arch = "arm")]
-pub const DRIVER_PATH: &str =
-    "https://edgedl.me.gvt1.com/edgedl/chrome/chrome-for-testing/120.0.6099.71/mac-arm64/chromedriver-mac-arm64.zip";
+#[cfg(target_arch = "aarch64")]
+pub const DRIVER_PLATFORM: &str = "mac-arm64";
+
+#[cfg(target_os = "linux")]
+pub const DRIVER_PLATFORM: &str = "linux64";
+
+#[cfg(target_os = "windows")]
+pub const DRIVER_PLATFORM: &str = "win64";
+
+// asked for --version in this order
+#[cfg(target_os = "macos")]
+pub const CHROME_BINARIES: &[&str] = &[
+    "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+    "/Applications/Chromium.app/Contents/MacOS/Chromium",
+];
 
 #[cfg(target_os = "linux")]
-pub const DRIVER_PATH: &str =
-    "https://edgedl.me.gvt1.com/edgedl/chrome/chrome-for-testing/120.0.6099.71/linux64/chromedriver-linux64.zip";
+pub const CHROME_BINARIES: &[&str] = &[
+    "google-chrome",
+    "google-chrome-stable",
+    "chromium",
+    "chromium-browser",
+];
 
 #[cfg(target_os = "windows")]
-pub const DRIVER_PATH: &str =
-    "https://edgedl.me.gvt1.com/edgedl/chrome/chrome-for-testing/120.0.6099.71/win64/chromedriver-win64.zip";
+pub const CHROME_BINARIES: &[&str] = &[];
 
 // ublock from github for chrome driver
 pub const UBLOCK_PATH: &str =
@@ -39,7 +58,21 @@ pub const FFMPEG_PATH: &str = "https://evermeet.cx/ffmpeg/ffmpeg-6.1.zip";
 pub const FFMPEG_PATH: &str =
     "static build: https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-amd64-static.tar.xz\n
     arch linux, other: sudo pacman -S ffmpeg\n
-    ubuntu, debian, other: sudo apt install ffmpeg";
+    ubuntu, debian, other: sudo apt install ffmpeg\n
+    or let the downloader install it with --download-ffmpeg";
+
+#[cfg(target_os = "linux")]
+#[cfg(target_arch = "x86_64")]
+pub const FFMPEG_STATIC_URL: Option<&str> = Some("https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-amd64-static.tar.xz");
+
+#[cfg(target_os = "linux")]
+#[cfg(target_arch = "aarch64")]
+pub const FFMPEG_STATIC_URL: Option<&str> = Some("https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-arm64-static.tar.xz");
+
+// no static build for the other arch, ffmpeg must come from the package manager
+#[cfg(target_os = "linux")]
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub const FFMPEG_STATIC_URL: Option<&str> = None;
 
 pub const HEADER: &str = r#"
   _   _      _                   _ _
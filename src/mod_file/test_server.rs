@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
+
+use crate::mod_file::utils_check::AllPath;
+
+// one request received by the server, `nth` count the requests made to the same path before it
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub nth: usize,
+    pub at: Instant,
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Reply {
+        Reply {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn status(status: u16) -> Reply {
+        Reply {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Reply {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+// http/1.1 on a free local port, one thread by connection, it live until the test process exit
+pub struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    pub fn start<F>(handler: F) -> TestServer
+        where
+            F: Fn(&Request) -> Reply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler = Arc::new(handler);
+
+        let seen = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let seen = Arc::clone(&seen);
+                let handler = Arc::clone(&handler);
+                thread::spawn(move || serve(stream, &seen, handler.as_ref()));
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+fn serve<F: Fn(&Request) -> Reply>(stream: TcpStream, seen: &Mutex<Vec<Request>>, handler: &F) {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+    let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line.trim().is_empty() => break,
+            Ok(_) => {
+                if let Some((key, value)) = line.split_once(':') {
+                    headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+                }
+            }
+        }
+    }
+
+    let request = {
+        let mut seen = seen.lock().unwrap();
+        let request = Request {
+            nth: seen.iter().filter(|r| r.path == path).count(),
+            path,
+            headers,
+            at: Instant::now(),
+        };
+        seen.push(request.clone());
+        request
+    };
+
+    let reply = handler(&request);
+    let mut head = format!(
+        "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    for (key, value) in &reply.headers {
+        head.push_str(&format!("{key}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    let mut stream = &stream;
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&reply.body);
+}
+
+// the folders of utils_check::check inside a test folder
+pub fn all_path(dir: &Path) -> AllPath {
+    AllPath {
+        exe_path: dir.join("neko_dl"),
+        ublock_destination: dir.join("utils/uBlock-Origin.crx"),
+        extract_path: dir.join("utils"),
+        tmp_dl: dir.join("tmp"),
+        resume_path: dir.join("tmp/resume"),
+        log_path: dir.join("logs"),
+        chrome_path: dir.join("utils/chromedriver"),
+        ffmpeg_path: dir.join("utils/ffmpeg"),
+        u_block_path: dir.join("utils/uBlock-Origin.crx"),
+    }
+}
+
+// empty folder of the system temp dir, unique by test
+pub fn temp_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("neko_dl-{}-{test}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -1,8 +1,8 @@
-use std::{error::Error, sync::Arc, thread};
+use std::{error::Error, panic, panic::AssertUnwindSafe, sync::Arc, thread};
 
-use crossbeam::queue::ArrayQueue;
+use crossbeam::queue::SegQueue;
 
-use crate::{mod_file::cmd_line_parser::Args, warn};
+use crate::{error, mod_file::cmd_line_parser::Args, warn};
 
 pub fn max_thread_check(new_args: &Args) -> Result<usize, Box<dyn Error>> {
     let mut thread = new_args.thread as usize;
@@ -15,9 +15,10 @@ pub fn max_thread_check(new_args: &Args) -> Result<usize, Box<dyn Error>> {
     Ok(thread)
 }
 
+// episodes are queued while the next ones are still resolved, the queue can't be sized up front
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    queue: Arc<ArrayQueue<Job>>,
+    queue: Arc<SegQueue<Job>>,
 }
 
 enum Job {
@@ -26,11 +27,11 @@ enum Job {
 }
 
 impl ThreadPool {
-    pub fn new(mut size: usize, capa: usize) -> ThreadPool {
+    pub fn new(mut size: usize) -> ThreadPool {
         if size <= 0 {
             size = 1
         }
-        let queue = Arc::new(ArrayQueue::<Job>::new(capa));
+        let queue = Arc::new(SegQueue::<Job>::new());
         let mut workers = Vec::with_capacity(size);
         for _ in 0..size {
             workers.push(Worker::new(Arc::clone(&queue)));
@@ -43,7 +44,7 @@ impl ThreadPool {
             F: FnOnce() + Send + 'static,
     {
         let job = Job::Task(Box::new(f));
-        let _ = &self.queue.push(job);
+        self.queue.push(job);
 
         for x in &mut self.workers {
             if let Some(a) = &x.thread {
@@ -56,7 +57,7 @@ impl ThreadPool {
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         for worker in &mut self.workers {
-            let _ = self.queue.push(Job::Terminate);
+            self.queue.push(Job::Terminate);
             if let Some(thread) = worker.thread.take() {
                 thread.thread().unpark();
             }
@@ -76,10 +77,15 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(queue: Arc<ArrayQueue<Job>>) -> Worker {
+    fn new(queue: Arc<SegQueue<Job>>) -> Worker {
         let thread = thread::spawn(move || loop {
             match queue.pop() {
-                Some(Job::Task(job)) => job(),
+                // a panicking job must not take the worker with it, the queue would never be emptied
+                Some(Job::Task(job)) => {
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("a download job panicked");
+                    }
+                }
                 Some(Job::Terminate) => break,
                 None => thread::park(),
             }
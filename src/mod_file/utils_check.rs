@@ -10,7 +10,8 @@ use std::{
 use reqwest::Client;
 
 use crate::{
-    error, info, mod_file::search::ProcessingUrl, mod_file::static_data, mod_file::utils_data,
+    error, info, mod_file::cmd_line_parser::Args, mod_file::driver_provision,
+    mod_file::search, mod_file::search::ProcessingUrl, mod_file::static_data, mod_file::utils_data, mod_file::web,
 };
 
 #[derive(Clone)]
@@ -19,6 +20,8 @@ pub struct AllPath {
     pub ublock_destination: PathBuf,
     pub extract_path: PathBuf,
     pub tmp_dl: PathBuf,
+    pub resume_path: PathBuf,
+    pub log_path: PathBuf,
     pub chrome_path: PathBuf,
     pub ffmpeg_path: PathBuf,
     pub u_block_path: PathBuf,
@@ -32,8 +35,10 @@ pub fn check() -> Result<AllPath, Box<dyn Error>> {
 
     let extract_path = exe_path.join(PathBuf::from("utils/"));
     let tmp_dl = exe_path.join(PathBuf::from("tmp/"));
+    let resume_path = tmp_dl.join(PathBuf::from("resume/"));
+    let log_path = exe_path.join(PathBuf::from("logs/"));
 
-    utils_data::remove_dir_contents(&tmp_dl);
+    utils_data::remove_tmp_files(&tmp_dl);
 
     // chrome driver
     #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -55,40 +60,40 @@ pub fn check() -> Result<AllPath, Box<dyn Error>> {
         ublock_destination,
         extract_path,
         tmp_dl,
+        resume_path,
+        log_path,
         chrome_path,
         ffmpeg_path,
         u_block_path,
     })
 }
 
-pub async fn confirm_chrome_ffmpeg_ublock_presence() -> Result<AllPath, Box<dyn Error>> {
-    let path = check()?;
+pub async fn confirm_chrome_ffmpeg_ublock_presence(args: &Args) -> Result<AllPath, Box<dyn Error>> {
+    #[allow(unused_mut)]
+    let mut path = check()?;
 
-    let mut chrome_check = false;
+    #[cfg(not(target_os = "linux"))]
     let mut ffmpeg_check = false;
     let mut ublock_check = false;
 
     fs::create_dir_all(&path.extract_path)?;
 
+    // linux has no ffmpeg.zip to unpack by hand, look in utils and PATH or download it
+    #[cfg(target_os = "linux")]
+    let ffmpeg_check = crate::mod_file::ffmpeg_provision::ensure_ffmpeg(&mut path, args).await?;
+
     for entry in fs::read_dir(&path.extract_path)? {
         if let Ok(x) = entry {
             if let Some(file_name) = x.file_name().to_str() {
                 #[cfg(target_os = "windows")]
                 if file_name.ends_with(".exe") {
-                    if file_name.contains("chromedriver") {
-                        chrome_check = true;
-                    }
                     if file_name.contains("ffmpeg") {
                         ffmpeg_check = true;
                     }
                 }
 
-                #[cfg(any(target_os = "macos", target_os = "linux"))]
+                #[cfg(target_os = "macos")]
                 if file_name.ends_with("") {
-                    if file_name.contains("chromedriver") {
-                        chrome_check = true;
-                    }
-
                     ffmpeg_check = true;
                 }
 
@@ -105,6 +110,8 @@ pub async fn confirm_chrome_ffmpeg_ublock_presence() -> Result<AllPath, Box<dyn
         download(static_data::UBLOCK_PATH, &path.ublock_destination).await.expect("Erreur lors du téléchargement de uBlock Origin.");
     }
 
+    let chrome_check = driver_provision::ensure_chromedriver(&path, args).await?;
+
     match ffmpeg_check && chrome_check && ublock_check {
         true => Ok(path),
         false => {
@@ -117,16 +124,16 @@ pub async fn confirm_chrome_ffmpeg_ublock_presence() -> Result<AllPath, Box<dyn
                 exit(0);
             } else if !chrome_check && ffmpeg_check {
                 error!(
-                    "Please download chrome wed driver then extract {} in utils folder here:\n{}",
+                    "Please install chrome, or download the chrome web driver of your chrome version then extract {} in utils folder here:\n{}",
                     path.chrome_path.display(),
-                    static_data::DRIVER_PATH
+                    static_data::CHROME_FOR_TESTING
                 );
                 exit(0);
             } else {
                 error!(
-                    "Please download chrome wed driver then extract {} in utils folder here:\n{}",
+                    "Please install chrome, or download the chrome web driver of your chrome version then extract {} in utils folder here:\n{}",
                     path.chrome_path.display(),
-                    static_data::DRIVER_PATH
+                    static_data::CHROME_FOR_TESTING
                 );
                 println!();
                 error!(
@@ -142,23 +149,139 @@ pub async fn confirm_chrome_ffmpeg_ublock_presence() -> Result<AllPath, Box<dyn
 
 pub async fn download(url: &str, destination: &PathBuf) -> Result<(), Box<dyn Error>> {
     info!("Download: {url}");
-    let response = Client::new().get(url).send().await?;
-    let archive_bytes = response.bytes().await?.to_vec();
+    let archive_bytes = web::fetch_bytes(&Client::new(), url).await?;
     let mut archive_file = File::create(destination)?;
     archive_file.write_all(&archive_bytes)?;
     Ok(())
 }
 
-fn _pick_season_list(
+// --select 1,3-5, numbers start at 1 like in candidates()
+pub fn pick_season_list(
     input: &str,
     processing_url: Vec<ProcessingUrl>,
 ) -> Result<Vec<ProcessingUrl>, Box<dyn Error>> {
-    let numbers: Vec<usize> = input
-        .split(|c: char| !c.is_digit(10))
-        .filter_map(|s| s.parse().ok())
-        .collect();
+    let mut numbers: Vec<usize> = vec![];
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()) {
+        let range = match part.split_once('-') {
+            Some((start, end)) => start.trim().parse::<usize>().and_then(|s| Ok(s..=end.trim().parse()?)),
+            None => part.parse::<usize>().map(|n| n..=n),
+        };
+        let Ok(range) = range else {
+            return Err(format!("\"{part}\" in --select isn't a number or a range like 3-5").into());
+        };
+        if range.is_empty() || *range.start() == 0 || *range.end() > processing_url.len() {
+            return Err(format!(
+                "\"{part}\" in --select is out of the {} seasons found:\n{}",
+                processing_url.len(),
+                candidates(&processing_url)
+            )
+                .into());
+        }
+        numbers.extend(range.filter(|n| !numbers.contains(n)).collect::<Vec<_>>());
+    }
+    if numbers.is_empty() {
+        return Err(format!("--select is empty, seasons found:\n{}", candidates(&processing_url)).into());
+    }
     Ok(numbers
         .iter()
-        .filter_map(|&number| processing_url.get(number - 1).map(|url| url.clone()))
+        .map(|&number| processing_url[number - 1].clone())
         .collect())
 }
+
+// --best-match keep the only season named like the keyword ( title or alias ), or the only season found
+pub fn pick_best_match(
+    keyword: &str,
+    processing_url: Vec<ProcessingUrl>,
+) -> Result<Vec<ProcessingUrl>, Box<dyn Error>> {
+    if processing_url.len() == 1 {
+        return Ok(processing_url);
+    }
+    let keyword = search::clean_string(keyword);
+    let exact: Vec<usize> = (0..processing_url.len())
+        .filter(|&i| {
+            let p = &processing_url[i];
+            [Some(&p.name), p.matched.as_ref()]
+                .into_iter()
+                .flatten()
+                .any(|name| search::clean_string(name).trim() == keyword.trim())
+        })
+        .collect();
+    if let [only] = exact[..] {
+        return Ok(vec![processing_url[only].clone()]);
+    }
+    let ambiguous: Vec<usize> = if exact.is_empty() { (0..processing_url.len()).collect() } else { exact };
+    Err(format!(
+        "--best-match is ambiguous, {} seasons match, pick them with --select:\n{}",
+        ambiguous.len(),
+        candidates_at(&processing_url, &ambiguous)
+    )
+        .into())
+}
+
+pub fn candidates(processing_url: &[ProcessingUrl]) -> String {
+    candidates_at(processing_url, &(0..processing_url.len()).collect::<Vec<_>>())
+}
+
+// only some seasons, still numbered by their place in the whole list so --select pick them
+fn candidates_at(processing_url: &[ProcessingUrl], indexes: &[usize]) -> String {
+    indexes
+        .iter()
+        .map(|&i| format!("  {}: {} ({})", i + 1, processing_url[i].label(), processing_url[i].ep))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn season(name: &str, matched: Option<&str>) -> ProcessingUrl {
+        ProcessingUrl {
+            name: name.to_string(),
+            ep: "12 Eps".to_string(),
+            url: format!("https://neko-sama.fr/anime/info/{}", search::clean_string(name).replace(' ', "-")),
+            genre: String::new(),
+            matched: matched.map(|m| m.to_string()),
+            score: None,
+        }
+    }
+
+    fn seasons() -> Vec<ProcessingUrl> {
+        vec![
+            season("Shingeki no Kyojin", Some("Attack on Titan")),
+            season("Kimetsu no Yaiba", None),
+            season("Kimetsu no Yaiba", Some("Demon Slayer")),
+            season("Kimetsu no Yaiba Movie", None),
+        ]
+    }
+
+    #[test]
+    fn best_match_keep_the_only_exact_title_or_alias() {
+        let picked = pick_best_match("attack on titan", seasons()).unwrap();
+        assert_eq!(picked.len(), 1);
+        assert_eq!(picked[0].name, "Shingeki no Kyojin");
+    }
+
+    #[test]
+    fn ambiguous_seasons_are_numbered_for_select() {
+        let e = pick_best_match("kimetsu no yaiba", seasons()).unwrap_err().to_string();
+        assert!(e.contains("2 seasons match"), "{e}");
+        assert!(e.contains("  2: Kimetsu no Yaiba (12 Eps)"), "{e}");
+        assert!(e.contains("  3: Kimetsu no Yaiba aka \"Demon Slayer\" (12 Eps)"), "{e}");
+        assert!(!e.contains("  1:"), "{e}");
+
+        // what the error say select the same seasons
+        let picked = pick_season_list("2,3", seasons()).unwrap();
+        assert!(picked.iter().all(|p| p.name == "Kimetsu no Yaiba"));
+    }
+
+    #[test]
+    fn select_read_numbers_and_ranges() {
+        let picked = pick_season_list("1, 3-4", seasons()).unwrap();
+        let names: Vec<&str> = picked.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Shingeki no Kyojin", "Kimetsu no Yaiba", "Kimetsu no Yaiba Movie"]);
+        assert!(pick_season_list("5", seasons()).is_err());
+        assert!(pick_season_list("0", seasons()).is_err());
+        assert!(pick_season_list("a", seasons()).is_err());
+    }
+}
@@ -42,14 +42,6 @@ pub fn time_to_human_time(time: Instant) -> String {
     format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
 }
 
-pub fn custom_sort(vec: &mut Vec<(PathBuf, PathBuf)>) {
-    vec.sort_by(|a, b| {
-        let num_a = extract_episode_number(&a.1.to_str().unwrap());
-        let num_b = extract_episode_number(&b.1.to_str().unwrap());
-        num_a.cmp(&num_b)
-    });
-}
-
 pub fn custom_sort_vlc(vec: &mut Vec<(PathBuf, String)>) {
     vec.sort_by(|a, b| {
         let num_a = extract_episode_number(&a.0.to_str().unwrap());
@@ -102,6 +94,13 @@ pub fn _path_length_windows(path: &str) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn remove_dir_contents<P: AsRef<Path>>(path: P) {
-    if let Ok(_) = fs::remove_dir_all(path) {}
+// keep sub folder like tmp/resume/, only the .m3u8 of the previous run are removed
+pub fn remove_tmp_files<P: AsRef<Path>>(path: P) {
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            if entry.path().is_file() {
+                if let Ok(_) = fs::remove_file(entry.path()) {}
+            }
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use std::{error::Error, path::{Path, PathBuf}};
+
+use serde_json::Value;
+
+use crate::mod_file::web;
+
+// seconds of video decoded at the end of the file, a truncated download fail here
+const TAIL_SECONDS: &str = "5";
+
+#[derive(Debug, Clone, Default)]
+pub struct Probe {
+    pub duration: Option<f64>,
+    pub has_video: bool,
+    pub has_audio: bool,
+}
+
+// Ok(None) mean ffprobe isn't available, the episode is kept but not verified
+pub fn verify(output: &Path, expected: Option<f64>, ffmpeg: &PathBuf) -> Result<Option<Probe>, String> {
+    let probe = match probe(output, ffmpeg) {
+        Ok(Some(probe)) => probe,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(format!("ffprobe can't read the file: {e}")),
+    };
+
+    if !probe.has_video {
+        return Err("no video stream".to_string());
+    }
+    if !probe.has_audio {
+        return Err("no audio stream".to_string());
+    }
+
+    if let (Some(actual), Some(expected)) = (probe.duration, expected) {
+        // the sum of EXTINF is rounded, allow 2s or 1% of difference
+        let tolerance = (expected * 0.01).max(2.0);
+        if (actual - expected).abs() > tolerance {
+            return Err(format!(
+                "duration {:.1}s instead of {:.1}s from the playlist",
+                actual, expected
+            ));
+        }
+    }
+
+    decode_tail(output, ffmpeg)?;
+    Ok(Some(probe))
+}
+
+fn probe(output: &Path, ffmpeg: &PathBuf) -> Result<Option<Probe>, Box<dyn Error>> {
+    let result = web::ffprobe_command(ffmpeg)
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration:stream=codec_type",
+            "-of",
+            "json",
+        ])
+        .arg(output)
+        .output();
+    let Ok(result) = result else {
+        return Ok(None);
+    };
+
+    if !result.status.success() {
+        return Err(String::from_utf8_lossy(&result.stderr).trim().to_string().into());
+    }
+
+    let json: Value = serde_json::from_slice(&result.stdout)?;
+    let streams = json["streams"].as_array().cloned().unwrap_or_default();
+    Ok(Some(Probe {
+        duration: json["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse().ok()),
+        has_video: streams.iter().any(|s| s["codec_type"] == "video"),
+        has_audio: streams.iter().any(|s| s["codec_type"] == "audio"),
+    }))
+}
+
+fn decode_tail(output: &Path, ffmpeg: &PathBuf) -> Result<(), String> {
+    let result = web::ffmpeg_command(ffmpeg)
+        .args(["-v", "error", "-sseof", &format!("-{TAIL_SECONDS}"), "-i"])
+        .arg(output)
+        .args(["-f", "null", "-"])
+        .output()
+        .map_err(|e| format!("Can't start ffmpeg: {e}"))?;
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if !result.status.success() || !stderr.trim().is_empty() {
+        return Err(format!(
+            "last {TAIL_SECONDS}s can't be decoded: {}",
+            stderr.lines().last().unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
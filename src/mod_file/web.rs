@@ -1,20 +1,126 @@
 use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    error::Error,
+    fmt::{Display, Formatter},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io,
+    io::{BufRead, BufReader},
     path::PathBuf,
     process::{Command, Stdio},
-    time::Instant,
+    sync::{Arc, OnceLock},
+    thread,
+    time::{Duration, Instant},
 };
 
-use reqwest::{Client, Response};
+use reqwest::{Client, header::RETRY_AFTER, Response, StatusCode};
+use tokio::{runtime::Handle, sync::Semaphore};
 
 use crate::{debug, warn};
+use crate::mod_file::{
+    cmd_line_parser::Args, download_progress::DownloadProgress, episode_outcome::EpisodeOutcome,
+    hls_downloader, journal::Journal, rate_limit,
+};
+
+const STDERR_TAIL: usize = 20;
+
+// shared by every episode worker, `connections` hold -t permits so segment fetch and ffmpeg share the same budget
+#[derive(Clone)]
+pub struct DownloadContext {
+    pub ffmpeg: PathBuf,
+    pub resume_path: PathBuf,
+    pub log_path: PathBuf,
+    pub client: Client,
+    pub handle: Handle,
+    pub connections: Arc<Semaphore>,
+    pub progress: DownloadProgress,
+    pub attempt: u8,
+    pub debug: bool,
+}
 
-pub fn download_build_video(path: &str, name: &str, _ffmpeg: &PathBuf, debug: &bool) -> i16 {
-    #[cfg(any(target_os = "macos", target_os = "linux"))]
+// on linux the path is the ffmpeg found or installed by ffmpeg_provision
+pub fn ffmpeg_command(_ffmpeg: &PathBuf) -> Command {
+    #[cfg(target_os = "macos")]
         let _ffmpeg = "ffmpeg";
 
+    Command::new(_ffmpeg)
+}
+
+// ffprobe come with ffmpeg, same folder or same PATH
+pub fn ffprobe_command(_ffmpeg: &PathBuf) -> Command {
+    #[cfg(target_os = "macos")]
+        let _ffprobe = PathBuf::from("ffprobe");
+    #[cfg(target_os = "linux")]
+        let _ffprobe = match _ffmpeg.with_file_name("ffprobe") {
+            ffprobe if ffprobe.is_file() => ffprobe,
+            _ => PathBuf::from("ffprobe"),
+        };
+    #[cfg(target_os = "windows")]
+        let _ffprobe = _ffmpeg.with_file_name("ffprobe.exe");
+
+    Command::new(_ffprobe)
+}
+
+pub struct FfmpegRun {
+    pub exit_code: Option<i32>,
+    pub stderr_tail: Vec<String>,
+}
+
+// keep the last lines of stderr for the summary and the log file, -v still print everything above the bars
+// stdout carry `-progress pipe:1` key=value lines when the caller ask for it
+pub fn run_ffmpeg<F: FnMut(&str, &str)>(mut process: Command, verbose: Option<&DownloadProgress>, mut on_progress: F) -> io::Result<FfmpegRun> {
+    // no stdin, with -t N every ffmpeg would read the keys typed in the prompts
+    let mut child = process
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take();
+    let verbose = verbose.cloned();
+    let stderr_reader = thread::spawn(move || {
+        let mut stderr_tail = VecDeque::with_capacity(STDERR_TAIL);
+        if let Some(stderr) = stderr {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if let Some(progress) = &verbose {
+                    progress.suspend(|| eprintln!("{line}"));
+                }
+                if stderr_tail.len() == STDERR_TAIL {
+                    stderr_tail.pop_front();
+                }
+                stderr_tail.push_back(line);
+            }
+        }
+        stderr_tail
+    });
+
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some((key, value)) = line.split_once('=') {
+                on_progress(key.trim(), value.trim());
+            }
+        }
+    }
+
+    Ok(FfmpegRun {
+        exit_code: child.wait()?.code(),
+        stderr_tail: stderr_reader.join().unwrap_or_default().into(),
+    })
+}
+
+pub fn download_build_video(path: &str, name: &str, ctx: &DownloadContext) -> EpisodeOutcome {
     let time = Instant::now();
-    let mut process = Command::new(_ffmpeg);
-    let args = [
+    let mut outcome = EpisodeOutcome::new(name, path);
+    let mut process = ffmpeg_command(&ctx.ffmpeg);
+    // sent by ffmpeg with every playlist, key and segment request
+    let headers = hls_downloader::playlist_headers(path);
+    if !headers.is_empty() {
+        let headers: String = headers.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect();
+        process.args(["-headers", &headers]);
+    }
+    // an unfinished episode from a previous run or a re-queue left <name>.mp4 behind
+    process.args([
+        "-y",
         "-protocol_whitelist",
         "file,http,https,tcp,tls,crypto",
         "-i",
@@ -25,38 +131,316 @@ pub fn download_build_video(path: &str, name: &str, _ffmpeg: &PathBuf, debug: &b
         "copy",
         "-c:a",
         "copy",
+        "-progress",
+        "pipe:1",
+        "-nostats",
         &name,
-    ];
-    if *debug {
+    ]);
+
+    // ffmpeg can't continue a partial file, the journal only flag the episode as unfinished
+    let journal_dir = Journal::dir(&ctx.resume_path, name);
+    if let Err(e) = Journal::load_or_new(&journal_dir, name, 0) {
+        warn!("Can't write journal for {}: {}", name, e);
+    }
+
+    // ffmpeg use one connection for the whole episode
+    let _permit = ctx.handle.block_on(ctx.connections.acquire()).expect("connection budget closed");
+
+    if ctx.debug {
         debug!("save path: {} output name: {}", path, name);
-        process
-            .args(args)
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Can't start ffmpeg")
-            .wait()
-            .expect("Error wait ffmpeg");
-    } else {
-        process.args(args).output().expect("Can't start ffmpeg");
     }
 
-    let end = time.elapsed().as_secs();
+    let progress = ctx.progress.episode(
+        &outcome.name,
+        outcome.duration.map(|d| (d * 1000.0) as u64).unwrap_or(0),
+    );
+    let mut out_time_ms = 0;
+    let mut total_size = 0;
+    let run = run_ffmpeg(process, ctx.debug.then_some(&ctx.progress), |key, value| {
+        match key {
+            "out_time_us" => out_time_ms = value.parse::<u64>().unwrap_or(out_time_ms * 1000) / 1000,
+            "total_size" => total_size = value.parse().unwrap_or(total_size),
+            // one block of progress end with this key
+            "progress" => progress.update(out_time_ms, total_size),
+            _ => {}
+        }
+    });
+    progress.done();
+
+    match run {
+        Ok(run) => {
+            outcome.exit_code = run.exit_code;
+            outcome.stderr_tail = run.stderr_tail;
+        }
+        Err(e) => outcome.error = Some(format!("Can't start ffmpeg: {e}")),
+    }
+
+    let outcome = outcome.finish(time, ctx);
+    if outcome.can_discard_journal() {
+        Journal::discard(&journal_dir);
+    }
+    outcome
+}
+
+pub struct RetryPolicy {
+    pub retries: u8,
+    pub base_delay: Duration,
+    pub timeout: Duration,
+}
+
+static RETRY_POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub fn init_retry_policy(args: &Args) {
+    let _ = RETRY_POLICY.set(RetryPolicy {
+        retries: args.retries,
+        base_delay: Duration::from_millis(args.retry_delay),
+        timeout: Duration::from_secs(args.timeout),
+    });
+}
+
+pub fn retry_policy() -> &'static RetryPolicy {
+    RETRY_POLICY.get_or_init(|| RetryPolicy {
+        retries: 3,
+        base_delay: Duration::from_millis(500),
+        timeout: Duration::from_secs(60),
+    })
+}
+
+// reqwest timeout cover the whole body, ours only the wait for the headers then for each chunk
+#[derive(Debug)]
+pub enum WebError {
+    Request(reqwest::Error),
+    Timeout(&'static str, Duration),
+}
+
+impl Display for WebError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebError::Request(e) => write!(f, "{e}"),
+            WebError::Timeout(what, after) => write!(f, "no {what} after {}s", after.as_secs()),
+        }
+    }
+}
+
+impl Error for WebError {}
+
+impl From<reqwest::Error> for WebError {
+    fn from(e: reqwest::Error) -> Self {
+        WebError::Request(e)
+    }
+}
+
+async fn within<T>(what: &'static str, timeout: Duration, call: impl Future<Output = Result<T, reqwest::Error>>) -> Result<T, WebError> {
+    match tokio::time::timeout(timeout, call).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(WebError::Timeout(what, timeout)),
+    }
+}
+
+// every network call go through here, 5xx 429 timeout and connection reset are retried with backoff
+// embed hosts often check Referer or Origin, extra headers are sent on every attempt
+pub async fn web_request_with_headers(client: &Client, url: &str, headers: &[(String, String)]) -> Result<Response, WebError> {
+    let policy = retry_policy();
+    let mut attempt = 0u8;
+    loop {
+        let result = send_once(client, url, headers, policy.timeout).await;
+        let retry_in = match &result {
+            Ok(resp) => retryable_status(resp),
+            Err(e) => retryable_error(e),
+        };
 
-    if end < 1 {
-        warn!("Episode {} are skipped or something went wrong, Please check download folder or use -v argument", name.split("/").last().unwrap())
+        match retry_in {
+            Some(retry_after) if attempt < policy.retries => {
+                attempt += 1;
+                let delay = retry_after.unwrap_or_else(|| backoff(policy.base_delay, attempt));
+                warn!(
+                    "{} failed ({}), retry {}/{} in {:.1}s",
+                    url,
+                    match &result {
+                        Ok(resp) => resp.status().to_string(),
+                        Err(e) => e.to_string(),
+                    },
+                    attempt,
+                    policy.retries,
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            _ => return result,
+        }
     }
+}
+
+// same as web_request but a body cut in the middle is also retried, error if status isn't 200
+pub async fn fetch_bytes(client: &Client, url: &str) -> Result<Vec<u8>, String> {
+    fetch_bytes_with_headers(client, url, &[]).await
+}
 
-    // thread return 1 via channel to update progress bar
-    1
+pub async fn fetch_bytes_with_headers(client: &Client, url: &str, headers: &[(String, String)]) -> Result<Vec<u8>, String> {
+    let policy = retry_policy();
+    let mut attempt = 0u8;
+    loop {
+        let resp = web_request_with_headers(client, url, headers).await.map_err(|e| format!("{url}: {e}"))?;
+        if resp.status() != StatusCode::OK {
+            return Err(format!("{url}: {}", resp.status()));
+        }
+        match read_body(resp).await {
+            Ok(body) => return Ok(body),
+            Err(e) if attempt < policy.retries && retryable_error(&e).is_some() => {
+                attempt += 1;
+                let delay = backoff(policy.base_delay, attempt);
+                warn!("{} body failed ({}), retry {}/{} in {:.1}s", url, e, attempt, policy.retries, delay.as_secs_f64());
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(format!("{url}: {e}")),
+        }
+    }
 }
 
-pub async fn web_request(client: &Client, url: &str) -> Result<Response, reqwest::Error> {
-    client
-        .get(url)
+async fn send_once(client: &Client, url: &str, headers: &[(String, String)], timeout: Duration) -> Result<Response, WebError> {
+    let mut request = client.get(url);
+    for (key, value) in headers {
+        request = request.header(key, value);
+    }
+    let request = request
         .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
         .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7")
         .header("Accept-Language", "en-US,en;q=0.9")
         .header("Upgrade-Insecure-Requests", 1)
-        .send()
-        .await
+        .send();
+    within("answer", timeout, request).await
+}
+
+// Some(None) retry with backoff, Some(Some(d)) retry after what the server asked
+fn retryable_status(resp: &Response) -> Option<Option<Duration>> {
+    let status = resp.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(|secs| Duration::from_secs(secs).min(MAX_DELAY));
+        Some(retry_after)
+    } else if status.is_server_error() {
+        Some(None)
+    } else {
+        None
+    }
+}
+
+fn retryable_error(e: &WebError) -> Option<Option<Duration>> {
+    match e {
+        WebError::Timeout(..) => Some(None),
+        WebError::Request(e) if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() => Some(None),
+        WebError::Request(_) => None,
+    }
+}
+
+// exponential backoff with up to 50% random jitter so threads don't retry together
+fn backoff(base_delay: Duration, attempt: u8) -> Duration {
+    let delay = base_delay
+        .saturating_mul(1 << (attempt.saturating_sub(1)).min(16))
+        .min(MAX_DELAY);
+    let jitter = RandomState::new().build_hasher().finish() % (delay.as_millis() as u64 / 2 + 1);
+    delay + Duration::from_millis(jitter)
+}
+
+// read the body chunk by chunk through the shared bandwidth limit, --timeout only apply between two chunks
+pub async fn read_body(mut resp: Response) -> Result<Vec<u8>, WebError> {
+    let mut body = vec![];
+    while let Some(chunk) = next_chunk(&mut resp).await? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+// None once the body is over, for what is too big to keep in memory
+pub async fn next_chunk(resp: &mut Response) -> Result<Option<Vec<u8>>, WebError> {
+    let chunk = within("data", retry_policy().timeout, resp.chunk()).await?;
+    if let Some(chunk) = &chunk {
+        rate_limit::throttle(chunk.len()).await;
+    }
+    Ok(chunk.map(Vec::from))
+}
+
+pub async fn read_text(resp: Response) -> Result<String, WebError> {
+    Ok(String::from_utf8_lossy(&read_body(resp).await?).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+
+    use super::*;
+    use crate::mod_file::test_server::{Reply, TestServer};
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_success() {
+        let server = TestServer::start(|r| match r.nth {
+            0 | 1 => Reply::status(503),
+            _ => Reply::ok("segment"),
+        });
+
+        let body = fetch_bytes(&Client::new(), &server.url("/seg.ts")).await.unwrap();
+
+        assert_eq!(body, b"segment");
+        assert_eq!(server.requests("/seg.ts").len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_honoured() {
+        let server = TestServer::start(|r| match r.nth {
+            0 => Reply::status(429).header("Retry-After", "2"),
+            _ => Reply::ok("index"),
+        });
+
+        let resp = web_request_with_headers(&Client::new(), &server.url("/index.json"), &[]).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let requests = server.requests("/index.json");
+        assert_eq!(requests.len(), 2);
+        // the backoff alone would retry in less than 1s
+        assert!(requests[1].at - requests[0].at >= Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_policy_limit() {
+        let server = TestServer::start(|_| Reply::status(503));
+
+        let result = fetch_bytes(&Client::new(), &server.url("/down")).await;
+
+        assert!(result.unwrap_err().contains("503"));
+        assert_eq!(server.requests("/down").len(), 1 + retry_policy().retries as usize);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = TestServer::start(|_| Reply::status(404));
+
+        let resp = web_request_with_headers(&Client::new(), &server.url("/missing"), &[]).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(server.requests("/missing").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_stalled_call_time_out() {
+        let stalled = future::pending::<Result<(), reqwest::Error>>();
+
+        let result = within("data", Duration::from_millis(20), stalled).await;
+
+        assert!(matches!(result, Err(WebError::Timeout("data", _))));
+        assert!(retryable_error(&result.unwrap_err()).is_some());
+    }
+
+    #[test]
+    fn backoff_grow_and_stay_under_the_cap() {
+        let base = Duration::from_millis(100);
+        assert!(backoff(base, 1) >= base && backoff(base, 1) <= base * 3 / 2);
+        assert!(backoff(base, 3) >= base * 4);
+        assert!(backoff(base, 16) <= MAX_DELAY * 3 / 2);
+    }
 }